@@ -6,6 +6,7 @@ use quicksilver::{
     input::Input,
     run, Result, Settings, Window,
 };
+use quicksilver_utils_async::frame_scheduler::FrameScheduler;
 use quicksilver_utils_ecs::*;
 use send_wrapper::SendWrapper;
 use specs::prelude::*;
@@ -97,9 +98,14 @@ async fn app(window: Window, gfx: Graphics, input: Input) -> Result<()> {
 
     room_system.setup_new_room(&mut world);
 
+    let mut scheduler = FrameScheduler::new(60);
+    scheduler.set_max_fps(Some(60));
+
     debug!("Entering main loop");
 
     loop {
+        let frame = scheduler.next_frame().await;
+
         let now: f64 = instant::now();
         *world.write_resource::<TimeContext>() = TimeContext { now };
 
@@ -113,8 +119,10 @@ async fn app(window: Window, gfx: Graphics, input: Input) -> Result<()> {
             }
         }
 
-        move_system.run_now(&world);
-        interaction_system.run_now(&world);
+        for _ in 0..frame.updates() {
+            move_system.run_now(&world);
+            interaction_system.run_now(&world);
+        }
 
         room_system.setup_new_room(&mut world);
 
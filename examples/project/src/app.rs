@@ -4,9 +4,10 @@ extern crate url;
 
 use log::{debug, info};
 use quicksilver_utils_async::{
+    frame_scheduler::FrameScheduler,
     // request::get_resource,
     task_context::TaskContext,
-    time::sleep_ms,
+    time::interval,
     websocket::{WebSocket, WebSocketMessage},
 };
 
@@ -16,27 +17,32 @@ use quicksilver::{
     Result, Window,
 };
 
+use std::time::Duration;
 use url::Url;
 
 #[derive(Debug)]
 enum CustomEvent {
     OnePingOnly,
     Ticked,
-    EchoResponse(WebSocketMessage),
     // Resource(String),
 }
 
+// published on its own channel rather than as a CustomEvent
+#[derive(Clone, Debug)]
+struct EchoResponse(WebSocketMessage);
+
 async fn tick_loop(task_context: TaskContext<'_, CustomEvent>) {
+    let mut ticks = interval(Duration::from_millis(500));
     loop {
+        ticks.tick().await;
         task_context.dispatch(CustomEvent::Ticked);
-        sleep_ms(500).await
     }
 }
 
 async fn read_websocket_loop(task_context: TaskContext<'_, CustomEvent>, ws: WebSocket) {
     loop {
         let message: WebSocketMessage = ws.receive().await.unwrap();
-        task_context.dispatch(CustomEvent::EchoResponse(message))
+        task_context.publish(EchoResponse(message)).unwrap()
     }
 }
 
@@ -55,15 +61,26 @@ pub async fn app(_window: Window, _gfx: Graphics, mut input: Input) -> Result<()
     let ws = WebSocket::connect(&Url::parse(url_string).unwrap())
         .await
         .unwrap();
-    task_context.spawn(read_websocket_loop(task_context.clone(), ws.clone()));
+    let echo_responses = task_context.subscribe::<EchoResponse>();
+    let websocket_reader =
+        task_context.spawn(read_websocket_loop(task_context.clone(), ws.clone()));
+
+    let mut scheduler = FrameScheduler::new(60);
+    scheduler.set_max_fps(Some(60));
 
     'main: loop {
+        scheduler.next_frame().await;
+
         task_context.run_until_stalled().await;
 
         for custom_event in task_context.drain().into_iter() {
             info!("CustomEvent: {:?}", custom_event)
         }
 
+        for echo_response in echo_responses.drain().into_iter() {
+            info!("EchoResponse: {:?}", echo_response)
+        }
+
         while let Some(ev) = input.next_event().await {
             if let BlindsEvent::KeyboardInput(key_event) = &ev {
                 if key_event.key() == Key::Escape && key_event.is_down() {
@@ -97,5 +114,7 @@ pub async fn app(_window: Window, _gfx: Graphics, mut input: Input) -> Result<()
         }
     }
 
+    websocket_reader.abort();
+
     Ok(())
 }
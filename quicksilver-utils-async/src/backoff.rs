@@ -0,0 +1,52 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use crate::time::Instant;
+
+/// Exponential backoff with jitter, shared by request retries and
+/// websocket reconnects
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    /// The fraction of each delay that is random, clamped to `[0, 1]`
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// The delay before retry number <retry>, counting from zero, with
+    /// <random> in `[0, 1)` picking the jitter
+    pub fn delay(&self, retry: u32, random: f64) -> Duration {
+        let base = self.initial.as_secs_f64() * self.multiplier.powi(retry as i32);
+        let base = base.min(self.max.as_secs_f64());
+        let jitter = self.jitter.clamp(0., 1.);
+        Duration::from_secs_f64(base * (1. - jitter * random))
+    }
+}
+
+/// xorshift, plenty for spreading out retries
+pub(crate) struct Rng {
+    state: Cell<u64>,
+}
+
+impl Rng {
+    pub fn new() -> Self {
+        let seed = RandomState::new().hash_one(Instant::now());
+        Rng {
+            state: Cell::new(seed | 1),
+        }
+    }
+
+    /// A number in `[0, 1)`
+    pub fn next_f64(&self) -> f64 {
+        let mut x = self.state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state.set(x);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
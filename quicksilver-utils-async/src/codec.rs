@@ -0,0 +1,144 @@
+//! # codec
+//!
+//! Encodings for typed request and response bodies, used by
+//! `ServiceClient::post`. JSON is available with the `json` feature and
+//! protobuf with the `protobuf` feature; other formats such as
+//! MessagePack or CBOR only need `Codec`, `Encode` and `Decode`
+//! implementations.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use bytes::Bytes;
+//! # use quicksilver_utils_async::codec::{Codec, Decode, Encode};
+//! # use quicksilver_utils_async::request::{RequestError, Result};
+//! /// Plain utf8 text bodies
+//! struct Text;
+//!
+//! impl Codec for Text {
+//!     fn content_type() -> &'static str {
+//!         "text/plain; charset=utf-8"
+//!     }
+//! }
+//!
+//! impl Encode<String> for Text {
+//!     fn encode(value: &String) -> Result<Bytes> {
+//!         Ok(Bytes::from(value.clone()))
+//!     }
+//! }
+//!
+//! impl Decode<String> for Text {
+//!     fn decode(body: &[u8]) -> Result<String> {
+//!         String::from_utf8(body.to_vec())
+//!             .map_err(|e| RequestError::Decode(format!("Invalid utf8 {}", e)))
+//!     }
+//! }
+//! ```
+
+use bytes::Bytes;
+
+#[cfg(any(feature = "json", feature = "protobuf"))]
+use crate::request::RequestError;
+use crate::request::Result;
+
+/// A body encoding
+pub trait Codec {
+    /// Sent as both the `Content-Type` and `Accept` header
+    fn content_type() -> &'static str;
+}
+
+/// Turns values of type `T` into request bodies
+pub trait Encode<T>: Codec {
+    fn encode(value: &T) -> Result<Bytes>;
+}
+
+/// Turns response bodies back into values of type `T`
+pub trait Decode<T>: Codec {
+    fn decode(body: &[u8]) -> Result<T>;
+}
+
+/// JSON bodies for any serde type, as in typed requests with
+/// `ServiceClient::post`:
+///
+/// ```no_run
+/// # use quicksilver_utils_async::codec::Json;
+/// # use quicksilver_utils_async::request::{ServiceClient, ServiceClientImpl};
+/// #[derive(serde::Serialize)]
+/// struct NewTodo {
+///     title: String,
+/// }
+///
+/// #[derive(serde::Deserialize)]
+/// struct Todo {
+///     id: u32,
+/// }
+///
+/// # async fn example() -> quicksilver_utils_async::request::Result<()> {
+/// let client = ServiceClientImpl::new();
+/// let new_todo = NewTodo { title: "feed the cat".to_string() };
+/// let todo: Todo = client
+///     .post::<_, _, Json>("https://jsonplaceholder.typicode.com/todos".parse().unwrap(), &new_todo)
+///     .await?;
+/// println!("created todo {}", todo.id);
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn content_type() -> &'static str {
+        "application/json"
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize> Encode<T> for Json {
+    fn encode(value: &T) -> Result<Bytes> {
+        serde_json::to_vec(value)
+            .map(Bytes::from)
+            .map_err(|e| RequestError::Encode(format!("Failed encoding json {}", e)))
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: serde::de::DeserializeOwned> Decode<T> for Json {
+    fn decode(body: &[u8]) -> Result<T> {
+        serde_json::from_slice(body)
+            .map_err(|e| RequestError::Decode(format!("Failed decoding json {}", e)))
+    }
+}
+
+/// Binary protobuf bodies for rust-protobuf messages
+///
+/// Sent as `application/octet-stream`, like `ServiceClient::post_proto`.
+#[cfg(feature = "protobuf")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Protobuf;
+
+#[cfg(feature = "protobuf")]
+impl Codec for Protobuf {
+    fn content_type() -> &'static str {
+        "application/octet-stream"
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<T: protobuf::Message> Encode<T> for Protobuf {
+    fn encode(value: &T) -> Result<Bytes> {
+        value
+            .write_to_bytes()
+            .map(Bytes::from)
+            .map_err(|e| RequestError::Encode(format!("Failed encoding protobuf {}", e)))
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<T: protobuf::Message> Decode<T> for Protobuf {
+    fn decode(body: &[u8]) -> Result<T> {
+        protobuf::parse_from_bytes(body)
+            .map_err(|e| RequestError::Decode(format!("Failed decoding protobuf {}", e)))
+    }
+}
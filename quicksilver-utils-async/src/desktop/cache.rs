@@ -0,0 +1,57 @@
+use crate::request::CacheStorage;
+use log::warn;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Keeps cached responses as files in a directory, created if needed
+#[derive(Clone, Debug)]
+pub struct DirectoryStorage {
+    dir: PathBuf,
+}
+
+impl DirectoryStorage {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        DirectoryStorage { dir: dir.into() }
+    }
+
+    // FNV-1a, stable between runs unlike the std hashers; collisions are
+    // caught because entries start with their key
+    fn path(&self, key: &str) -> PathBuf {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in key.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        self.dir.join(format!("{:016x}", hash))
+    }
+}
+
+impl CacheStorage for DirectoryStorage {
+    fn load(&self, key: &str) -> Option<Vec<u8>> {
+        match fs::read(self.path(key)) {
+            Ok(entry) => Some(entry),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Failed reading cached {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    fn store(&self, key: &str, entry: &[u8]) {
+        let stored = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.path(key), entry));
+        if let Err(e) = stored {
+            warn!("Failed caching {}: {}", key, e);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                warn!("Failed removing cached {}: {}", key, e)
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! The client side of the websocket opening handshake, RFC 6455
+//! section 4.1
+//!
+//! soketto writes the request and checks the response, but sends the
+//! configured headers as they are, so they are checked here first.
+
+use futures_io::{AsyncRead, AsyncWrite};
+use http::header::{
+    HeaderName, CONNECTION, HOST, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY,
+    SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use soketto::connection::Builder;
+use soketto::handshake::client::{Client, Header, ServerResponse};
+use url::{Position, Url};
+
+use crate::websocket::{WebSocketConfig, WebSocketError};
+
+/// The headers the handshake sets itself
+const HANDSHAKE_HEADERS: &[HeaderName] = &[
+    HOST,
+    UPGRADE,
+    CONNECTION,
+    SEC_WEBSOCKET_KEY,
+    SEC_WEBSOCKET_VERSION,
+    SEC_WEBSOCKET_PROTOCOL,
+    SEC_WEBSOCKET_EXTENSIONS,
+];
+
+/// How the server answered the handshake
+pub(crate) enum Handshake<T> {
+    /// Frames follow, read by <builder>'s connection
+    Accepted {
+        protocol: Option<String>,
+        builder: Builder<T>,
+    },
+    Redirect {
+        status_code: u16,
+        location: String,
+    },
+    Rejected {
+        status_code: u16,
+    },
+}
+
+fn handshake_error(message: impl std::fmt::Display) -> WebSocketError {
+    WebSocketError::NativeError(format!("Handshake error: {}", message))
+}
+
+/// A token as HTTP defines it, which is all a subprotocol may be
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+/// Fail on a header or subprotocol in <config> that could break out
+/// of its line, or that the handshake sets itself
+pub(crate) fn check(config: &WebSocketConfig) -> Result<(), WebSocketError> {
+    if let Some(protocol) = config.protocols.iter().find(|protocol| !is_token(protocol)) {
+        return Err(handshake_error(format!(
+            "invalid subprotocol {:?}",
+            protocol
+        )));
+    }
+    for (name, value) in config.headers.iter() {
+        if HANDSHAKE_HEADERS.contains(name) {
+            return Err(handshake_error(format!("{} is set by the handshake", name)));
+        }
+        if value
+            .as_bytes()
+            .iter()
+            .any(|byte| *byte == b'\r' || *byte == b'\n')
+        {
+            return Err(handshake_error(format!(
+                "line break in the {} header",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Shake hands over <stream> to open a websocket to <url>, with the
+/// headers of <config> already checked
+pub(crate) async fn handshake<T: AsyncRead + AsyncWrite + Unpin>(
+    stream: T,
+    url: &Url,
+    config: &WebSocketConfig,
+) -> Result<Handshake<T>, WebSocketError> {
+    let mut host = url
+        .host_str()
+        .ok_or_else(|| WebSocketError::NativeError(format!("No host in {}", url)))?
+        .to_string();
+    if let Some(port) = url.port() {
+        host.push_str(&format!(":{}", port));
+    }
+    let resource = &url[Position::BeforePath..Position::AfterQuery];
+    let headers: Vec<Header> = config
+        .headers
+        .iter()
+        .map(|(name, value)| Header {
+            name: name.as_str(),
+            value: value.as_bytes(),
+        })
+        .collect();
+
+    let mut client = Client::new(stream, &host, resource);
+    client.set_headers(&headers);
+    for protocol in &config.protocols {
+        client.add_protocol(protocol);
+    }
+    Ok(match client.handshake().await.map_err(handshake_error)? {
+        ServerResponse::Accepted { protocol } => Handshake::Accepted {
+            protocol,
+            builder: client.into_builder(),
+        },
+        ServerResponse::Redirect {
+            status_code,
+            location,
+        } => Handshake::Redirect {
+            status_code,
+            location,
+        },
+        ServerResponse::Rejected { status_code } => Handshake::Rejected { status_code },
+    })
+}
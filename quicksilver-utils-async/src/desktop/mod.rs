@@ -1,6 +1,8 @@
 extern crate async_std;
 extern crate surf;
 
+pub(crate) mod cache;
+mod handshake;
 pub(crate) mod request;
 pub(crate) mod time;
 mod tls;
@@ -1,41 +1,182 @@
-use crate::request::{RequestError, Result, ServiceClient};
+use crate::request::{
+    build_response, HttpTransport, Request, RequestError, Result, StreamingResponse,
+};
 use async_trait::async_trait;
 use bytes::Bytes;
-use http::Uri;
+use futures_preview::io::AsyncRead;
+use futures_util::future::{poll_fn, BoxFuture};
+use futures_util::stream::{self, StreamExt};
+use http::Method;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use surf::middleware::{Body, HttpClient, Middleware, Next};
 
-pub struct ServiceClientImpl {
-    auth_token: Option<String>,
-}
+const CHUNK_SIZE: usize = 8 * 1024;
+
+// surf only takes &'static header names, so arbitrary headers are added
+// to the underlying request instead, replacing surf's own defaults such
+// as the octet-stream Content-Type of `body_bytes`
+struct ExtraHeaders(surf::http::HeaderMap);
 
-#[async_trait]
-impl ServiceClient for ServiceClientImpl {
-    // TODO: build a surf client once and re-use?
-    fn new() -> Self {
-        let auth_token = None;
-        ServiceClientImpl { auth_token }
+impl<C: HttpClient> Middleware<C> for ExtraHeaders {
+    fn handle<'a>(
+        &'a self,
+        mut request: surf::middleware::Request,
+        client: C,
+        next: Next<'a, C>,
+    ) -> BoxFuture<'a, std::result::Result<surf::middleware::Response, surf::Exception>> {
+        let headers = request.headers_mut();
+        for name in self.0.keys() {
+            headers.remove(name);
+        }
+        for (name, value) in self.0.iter() {
+            headers.append(name, value.clone());
+        }
+        next.run(request, client)
     }
+}
+
+// surf's own curl client always hands curl a body, so a GET without
+// one still goes out with an empty chunked body. This one only sends a
+// body when the transport set a Content-Length, which curl then uses.
+#[derive(Clone, Debug)]
+struct CurlClient(Arc<isahc::HttpClient>);
 
-    fn set_auth_token(&mut self, auth_token: &str) {
-        self.auth_token = Some(auth_token.to_string())
+impl HttpClient for CurlClient {
+    type Error = isahc::Error;
+
+    fn send(
+        &self,
+        request: surf::middleware::Request,
+    ) -> BoxFuture<'static, std::result::Result<surf::middleware::Response, isahc::Error>> {
+        let client = self.0.clone();
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = if parts
+                .headers
+                .contains_key(surf::http::header::CONTENT_LENGTH)
+            {
+                isahc::Body::reader(body)
+            } else {
+                isahc::Body::empty()
+            };
+            let response = client
+                .send_async(surf::http::Request::from_parts(parts, body))
+                .await?;
+            let (parts, body) = response.into_parts();
+            Ok(surf::http::Response::from_parts(
+                parts,
+                Body::from_reader(body),
+            ))
+        })
     }
+}
 
-    async fn post_raw(&self, uri: Uri, request_body: Bytes) -> Result<Bytes> {
-        let raw_uri = format!("{}", uri);
-        let mut request = surf::post(raw_uri)
-            .set_header("Accept", "application/octet-stream")
-            .set_header("Content-Type", "application/octet-stream");
+/// Sends requests with surf
+///
+/// Headers set on the request replace surf's defaults, and a GET or
+/// HEAD without a body goes out without any body headers. Clones share
+/// one curl client, and so its connection pool.
+///
+/// ```no_run
+/// # use quicksilver_utils_async::request::*;
+/// # async fn example() -> Result<()> {
+/// let client = Client::with_transport(SurfTransport::new());
+/// let response = client.get("https://example.com/scores".parse().unwrap()).send().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct SurfTransport {
+    client: Arc<isahc::HttpClient>,
+}
+
+impl SurfTransport {
+    pub fn new() -> Self {
+        let client = isahc::HttpClient::new().expect("Failed creating the curl client");
+        SurfTransport::with_client(client)
+    }
 
-        if let Some(auth_token) = &self.auth_token {
-            request = request.set_header("Authorization", auth_token);
+    /// Send with <client>, such as one built with its own proxy or
+    /// connection limits
+    pub fn with_client(client: isahc::HttpClient) -> Self {
+        SurfTransport {
+            client: Arc::new(client),
         }
+    }
+}
+
+impl Default for SurfTransport {
+    fn default() -> Self {
+        SurfTransport::new()
+    }
+}
+
+#[async_trait(?Send)]
+impl HttpTransport for SurfTransport {
+    async fn send(&self, request: Request) -> Result<StreamingResponse> {
+        let (parts, body) = request.into_parts();
 
-        request = request.body_bytes(request_body);
+        let method = surf::http::Method::from_bytes(parts.method.as_str().as_bytes())
+            .map_err(|e| RequestError::Encode(format!("Invalid method {}", e)))?;
+        let url = format!("{}", parts.uri)
+            .parse()
+            .map_err(|e| RequestError::Encode(format!("Invalid url {}", e)))?;
 
-        let response_bytes_vec = request
-            .recv_bytes()
+        let mut headers = surf::http::HeaderMap::new();
+        for (name, value) in parts.headers.iter() {
+            headers.append(
+                surf::http::header::HeaderName::from_bytes(name.as_str().as_bytes())
+                    .map_err(|e| RequestError::Encode(format!("Invalid header {}", e)))?,
+                surf::http::header::HeaderValue::from_bytes(value.as_bytes())
+                    .map_err(|e| RequestError::Encode(format!("Invalid header {}", e)))?,
+            );
+        }
+        let mut request = surf::Request::with_client(method, url, CurlClient(self.client.clone()));
+        // a GET or HEAD without a body says nothing about one
+        if !body.is_empty() || (parts.method != Method::GET && parts.method != Method::HEAD) {
+            headers.insert(surf::http::header::CONTENT_LENGTH, body.len().into());
+            request = request.body_bytes(body);
+        }
+
+        let mut response = request
+            .middleware(ExtraHeaders(headers))
             .await
-            .map_err(|e| RequestError::NativeError(format!("Failed making hyper request {}", e)))?;
+            .map_err(|e| match e.downcast_ref::<io::Error>() {
+                Some(e) if e.kind() == io::ErrorKind::TimedOut => RequestError::Timeout,
+                _ => RequestError::Transport(format!("Failed making hyper request {}", e)),
+            })?;
+
+        let status = response.status().as_u16();
+        let headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        let body = stream::unfold(Some(response), |response| async move {
+            let mut response = response?;
+            let mut chunk = vec![0; CHUNK_SIZE];
+            match poll_fn(|cx| Pin::new(&mut response).poll_read(cx, &mut chunk)).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Some((Ok(Bytes::from(chunk)), Some(response)))
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    Some((Err(RequestError::Timeout), None))
+                }
+                Err(e) => Some((
+                    Err(RequestError::Transport(format!(
+                        "Failed reading body {}",
+                        e
+                    ))),
+                    None,
+                )),
+            }
+        });
 
-        Ok(Bytes::from(response_bytes_vec))
+        build_response(status, headers, body.boxed_local())
     }
 }
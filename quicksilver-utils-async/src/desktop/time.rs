@@ -1,5 +1,18 @@
 use async_std::task::sleep;
-use std::time::Duration;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static ORIGIN: OnceLock<Instant> = OnceLock::new();
+
+pub(crate) fn now() -> Duration {
+    ORIGIN.get_or_init(Instant::now).elapsed()
+}
+
+pub(crate) fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
 
 pub(crate) async fn sleep_ms(ms: u32) {
     sleep(Duration::from_millis(ms as u64)).await
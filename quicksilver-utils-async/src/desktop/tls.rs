@@ -1,9 +1,68 @@
-use rustls::ClientConfig;
+use log::warn;
+use rustls::{Certificate, ClientConfig, RootCertStore};
 use rustls_native_certs::load_native_certs;
 
-pub fn client_config() -> ClientConfig {
-    let mut config = ClientConfig::new();
-    let native_certs = load_native_certs().expect("Could not load platform certs");
-    config.root_store = native_certs;
-    config
+use crate::websocket::{WebSocketConfig, WebSocketError};
+
+#[cfg(feature = "dangerous-tls")]
+use rustls::{ServerCertVerified, ServerCertVerifier, TLSError};
+#[cfg(feature = "dangerous-tls")]
+use std::sync::Arc;
+#[cfg(feature = "dangerous-tls")]
+use webpki::DNSNameRef;
+
+/// Trusts every certificate, for `WebSocketConfig::danger_accept_invalid_certs`
+#[cfg(feature = "dangerous-tls")]
+struct AcceptAnyCertificate;
+
+#[cfg(feature = "dangerous-tls")]
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+pub fn client_config(config: &WebSocketConfig) -> Result<ClientConfig, WebSocketError> {
+    let mut client_config = ClientConfig::new();
+    client_config.root_store = if config.skip_built_in_roots {
+        RootCertStore::empty()
+    } else {
+        match load_native_certs() {
+            Ok(native_certs) => native_certs,
+            // the ones that did load are still good
+            Err((native_certs, e)) => {
+                warn!("Could not load platform certs: {}", e);
+                native_certs.unwrap_or_else(RootCertStore::empty)
+            }
+        }
+    };
+    for certificate in &config.root_certificates {
+        client_config
+            .root_store
+            .add(&Certificate(certificate.clone()))
+            .map_err(|e| WebSocketError::NativeError(format!("Invalid root certificate: {}", e)))?;
+    }
+
+    #[cfg(feature = "dangerous-tls")]
+    {
+        if config.accept_invalid_certs {
+            warn!("Accepting invalid certificates");
+            client_config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(AcceptAnyCertificate));
+            return Ok(client_config);
+        }
+    }
+    if client_config.root_store.is_empty() {
+        return Err(WebSocketError::NativeError(
+            "No root certificates to trust".to_string(),
+        ));
+    }
+    Ok(client_config)
 }
@@ -4,34 +4,45 @@ use url::Url;
 use async_std::net::TcpStream;
 use async_tls::TlsConnector;
 use bytes::Bytes;
+use futures_util::lock::Mutex;
 use soketto::{
-    connection::{Error as ConnectionError, Receiver, Sender},
-    handshake::{Client, Error as HandshakeError, ServerResponse},
+    connection::{CloseReason, Error as ConnectionError, Receiver, Sender},
+    data::{ByteSlice125, Data, Incoming},
 };
-use std::cell::RefCell;
+use std::cell::Cell;
+use std::convert::TryFrom;
 use std::io::Error as IoError;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use log::{debug, trace, warn};
 
+use super::handshake::{check, handshake, Handshake};
 use super::tls::client_config;
-use crate::websocket::{WebSocketError, WebSocketMessage};
+use crate::time::Instant;
+use crate::websocket::{within, WebSocketConfig, WebSocketError, WebSocketMessage};
 
+/// Clones share the connection, and the locks make concurrent sends, or
+/// concurrent receives, take turns
 #[derive(Clone)]
 pub struct AsyncWebSocket {
-    sender: Arc<RefCell<Sender<Box<dyn AsyncStream>>>>,
-    receiver: Arc<RefCell<Receiver<Box<dyn AsyncStream>>>>,
+    sender: Rc<Mutex<Sender<Box<dyn AsyncStream>>>>,
+    receiver: Rc<Mutex<Receiver<Box<dyn AsyncStream>>>>,
+    protocol: Option<String>,
+    /// The number of the ping the last pong frame answered, and when it
+    /// arrived
+    last_pong: Rc<Cell<Option<(u64, Instant)>>>,
 }
 
-impl From<HandshakeError> for WebSocketError {
-    fn from(err: HandshakeError) -> Self {
-        WebSocketError::NativeError(format!("Handshake error: {}", err))
-    }
-}
+/// How many redirects `connect` follows before giving up
+const MAX_REDIRECTS: usize = 5;
 
 impl From<ConnectionError> for WebSocketError {
     fn from(err: ConnectionError) -> Self {
-        WebSocketError::NativeError(format!("Connection error: {}", err))
+        match err {
+            ConnectionError::Closed => WebSocketError::StateClosed,
+            err => WebSocketError::NativeError(format!("Connection error: {}", err)),
+        }
     }
 }
 
@@ -45,21 +56,42 @@ trait AsyncStream: AsyncRead + AsyncWrite + Unpin {}
 
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncStream for T {}
 
-async fn client(url: &Url) -> Result<Client<'_, Box<dyn AsyncStream>>, WebSocketError> {
+/// Where a redirect from <url> to <location> goes, as a websocket url
+fn redirect(url: &Url, location: &str) -> Result<Url, WebSocketError> {
+    let mut target = url.join(location).map_err(|e| {
+        WebSocketError::NativeError(format!("Invalid redirect to {}: {}", location, e))
+    })?;
+    let scheme = match target.scheme() {
+        "http" => "ws",
+        "https" => "wss",
+        scheme => scheme,
+    }
+    .to_string();
+    let _ = target.set_scheme(&scheme);
+    Ok(target)
+}
+
+fn host(url: &Url) -> Result<&str, WebSocketError> {
+    url.host_str()
+        .ok_or_else(|| WebSocketError::NativeError(format!("No host in {}", url)))
+}
+
+/// Connect to the server at <url>, with TLS for `wss`
+async fn stream(
+    url: &Url,
+    config: &WebSocketConfig,
+) -> Result<Box<dyn AsyncStream>, WebSocketError> {
     debug!("Creating client to url {}", url);
     let port = url.port_or_known_default();
-    let host = url.host_str().expect("url host");
-    let path = url.path();
+    let host = host(url)?;
     let scheme = url.scheme();
-    let addresses = url.socket_addrs(|| port).expect("url lookup via dns");
+    let addresses = url.socket_addrs(|| port)?;
 
     trace!("Possible addresses {:?}", addresses);
-    let address = addresses[0];
-
-    trace!("Connecting to address {}", address);
     let transport_stream = {
         let mut connected_stream: Option<TcpStream> = None;
         for address in addresses {
+            trace!("Connecting to address {}", address);
             let attempted_stream = TcpStream::connect(address).await;
             match attempted_stream {
                 Ok(stream) => {
@@ -81,70 +113,143 @@ async fn client(url: &Url) -> Result<Client<'_, Box<dyn AsyncStream>>, WebSocket
     };
 
     trace!("Scheme: {}", scheme);
-    let boxed_stream: Box<dyn AsyncStream> = if scheme == "wss" {
-        debug!(
-            "Starting TLS handshake for secure websocket with domain {}",
-            host
-        );
-
-        let config = client_config();
-
-        let connector: TlsConnector = TlsConnector::from(Arc::new(config));
-        trace!("Created connector");
-
-        let handshake = connector.connect(host, transport_stream);
-        let tls_stream = handshake.await?;
-        debug!("Completed TLS handshake");
-        Box::new(tls_stream)
-    } else {
-        Box::new(transport_stream)
-    };
+    if scheme != "wss" {
+        return Ok(Box::new(transport_stream));
+    }
+    debug!(
+        "Starting TLS handshake for secure websocket with domain {}",
+        host
+    );
 
-    Ok(Client::new(boxed_stream, host, path))
-}
+    let tls_config = client_config(config)?;
 
-impl AsyncWebSocket {
-    pub async fn connect(url: &Url) -> Result<Self, WebSocketError> {
-        let mut client = client(url).await?;
+    let connector: TlsConnector = TlsConnector::from(Arc::new(tls_config));
+    trace!("Created connector");
 
-        let (sender, receiver) = match client.handshake().await? {
-            ServerResponse::Accepted { .. } => client.into_builder().finish(),
-            ServerResponse::Redirect { .. } => unimplemented!("follow location URL"),
-            ServerResponse::Rejected { .. } => unimplemented!("handle failure"),
-        };
+    let handshake = connector.connect(host, transport_stream);
+    let tls_stream = handshake.await?;
+    debug!("Completed TLS handshake");
+    Ok(Box::new(tls_stream))
+}
 
-        let sender = Arc::new(RefCell::new(sender));
-        let receiver = Arc::new(RefCell::new(receiver));
+impl AsyncWebSocket {
+    pub async fn connect(url: &Url, config: &WebSocketConfig) -> Result<Self, WebSocketError> {
+        check(config)?;
+        let mut url = url.clone();
+        for _ in 0..=MAX_REDIRECTS {
+            let location = {
+                let stream = within(config.connect_timeout, stream(&url, config)).await?;
+
+                let handshake = handshake(stream, &url, config);
+                match within(config.handshake_timeout, handshake).await? {
+                    Handshake::Accepted { protocol, builder } => {
+                        let (sender, receiver) = builder.finish();
+
+                        let sender = Rc::new(Mutex::new(sender));
+                        let receiver = Rc::new(Mutex::new(receiver));
+
+                        return Ok(AsyncWebSocket {
+                            sender,
+                            receiver,
+                            protocol,
+                            last_pong: Rc::new(Cell::new(None)),
+                        });
+                    }
+                    Handshake::Redirect {
+                        status_code,
+                        location,
+                    } => {
+                        debug!("Redirected ({}) to {}", status_code, location);
+                        location
+                    }
+                    Handshake::Rejected { status_code } => {
+                        return Err(WebSocketError::Rejected {
+                            status: status_code,
+                        })
+                    }
+                }
+            };
+            url = redirect(&url, &location)?;
+        }
+        Err(WebSocketError::NativeError(format!(
+            "Too many redirects, the last to {}",
+            url
+        )))
+    }
 
-        Ok(AsyncWebSocket { sender, receiver })
+    pub fn protocol(&self) -> Option<String> {
+        self.protocol.clone()
     }
 
     pub async fn send(&self, msg: &WebSocketMessage) -> Result<(), WebSocketError> {
-        let mut sender = self.sender.borrow_mut();
+        let mut sender = self.sender.lock().await;
         match msg {
             WebSocketMessage::String(s) => sender.send_text(s).await?,
             WebSocketMessage::Binary(b) => sender.send_binary(b).await?,
+            // soketto only closes with 1000, fail rather than send another
+            WebSocketMessage::Close { code, reason } if *code != 1000 || !reason.is_empty() => {
+                return Err(WebSocketError::NativeError(format!(
+                    "Can't close with {} {:?}, only with 1000 and no reason on desktop",
+                    code, reason
+                )))
+            }
+            WebSocketMessage::Close { .. } => {
+                sender.close().await?;
+                return Ok(());
+            }
         }
         sender.flush().await?; // otherwise it just sits there, which is just surprising for casual users
         Ok(())
     }
 
+    /// Send a ping frame numbered <id>, the server answers it by itself
+    pub async fn ping(&self, id: u64, _message: &WebSocketMessage) -> Result<(), WebSocketError> {
+        let mut sender = self.sender.lock().await;
+        let id = id.to_be_bytes();
+        let payload = ByteSlice125::try_from(&id[..]).expect("a number fits in a ping");
+        sender.send_ping(payload).await?;
+        sender.flush().await?;
+        Ok(())
+    }
+
+    pub fn last_pong(&self) -> Option<(u64, Instant)> {
+        self.last_pong.get()
+    }
+
     pub async fn receive(&self) -> Result<WebSocketMessage, WebSocketError> {
-        let data = self.receiver.borrow_mut().receive_data().await?;
-        let message = if data.is_binary() {
-            let data_slice: &[u8] = data.as_ref();
-            WebSocketMessage::Binary(Bytes::copy_from_slice(data_slice))
-        } else {
-            let data_slice: &[u8] = data.as_ref();
-            let s = String::from_utf8(Vec::from(data_slice))
-                .map_err(|_| WebSocketError::NativeError("invalid ut8".to_string()))?;
-            WebSocketMessage::String(s)
+        let mut receiver = self.receiver.lock().await;
+        let mut message = Vec::new();
+        let data = loop {
+            match receiver.receive(&mut message).await? {
+                Incoming::Data(data) => break data,
+                Incoming::Pong(payload) => {
+                    // unsolicited pongs may carry anything
+                    if let Ok(id) = <[u8; 8]>::try_from(payload) {
+                        self.last_pong
+                            .set(Some((u64::from_be_bytes(id), Instant::now())))
+                    }
+                }
+                // the server's close frame, which soketto already answered
+                Incoming::Closed(CloseReason { code, descr }) => {
+                    return Ok(WebSocketMessage::Close {
+                        code,
+                        reason: descr.unwrap_or_default(),
+                    })
+                }
+            }
+        };
+        let message = match data {
+            Data::Binary(_) => WebSocketMessage::Binary(Bytes::from(message)),
+            Data::Text(_) => WebSocketMessage::String(
+                String::from_utf8(message)
+                    .map_err(|_| WebSocketError::NativeError("invalid ut8".to_string()))?,
+            ),
         };
         Ok(message)
     }
 
     pub async fn close(&self) -> Result<(), WebSocketError> {
-        self.sender.borrow_mut().close().await?;
+        self.sender.lock().await.close().await?;
         Ok(())
     }
 }
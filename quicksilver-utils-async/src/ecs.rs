@@ -0,0 +1,136 @@
+//! # ecs
+//!
+//! Glue between a `TaskContext` and a specs `World`, enabled with the
+//! `specs` feature.
+//!
+//! The context goes into the world as a `TaskContextResource`, so
+//! systems can spawn async work. Events that tasks publish on the
+//! context's bus are copied into a `TaskEvents<T>` resource once per
+//! frame by a `DrainTaskEvents<T>` system, so other systems can read
+//! them like any other resource.
+//!
+//! The context is only usable from the thread that created it, so run
+//! systems that touch it with `run_now`, or add them to a dispatcher
+//! with `with_thread_local`.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use quicksilver_utils_async::ecs::*;
+//! # use quicksilver_utils_async::task_context::TaskContext;
+//! # use specs_ecs::prelude::*;
+//! # use async_std::task::block_on;
+//! #[derive(Clone, Debug, PartialEq)]
+//! struct ServerMessage(String);
+//!
+//! struct SpawnGreeting;
+//!
+//! impl<'a> System<'a> for SpawnGreeting {
+//!     type SystemData = WriteExpect<'a, TaskContextResource>;
+//!
+//!     fn run(&mut self, mut tasks: Self::SystemData) {
+//!         let task_context = tasks.task_context.clone();
+//!         tasks.task_context.spawn(async move {
+//!             task_context.publish(ServerMessage("hello".to_string())).unwrap();
+//!         });
+//!     }
+//! }
+//!
+//! let mut world = World::new();
+//! let task_context: TaskContext<'static> = TaskContext::new();
+//! insert_task_context(&mut world, task_context.clone());
+//! world.insert(TaskEvents::<ServerMessage>::default());
+//! let mut drain_system = DrainTaskEvents::<ServerMessage>::new(&task_context);
+//!
+//! SpawnGreeting.run_now(&world);
+//! block_on(run_until_stalled::<()>(&world));
+//! drain_system.run_now(&world);
+//!
+//! for message in world.read_resource::<TaskEvents<ServerMessage>>().iter() {
+//!     println!("{:?}", message);
+//! }
+//! ```
+
+use send_wrapper::SendWrapper;
+use specs_ecs::{ReadExpect, System, World, WriteExpect};
+
+use crate::event_bus::Subscriber;
+use crate::task_context::TaskContext;
+
+/// The world's `TaskContext`, put there by `insert_task_context`
+///
+/// It has no default, so systems fetch it with `WriteExpect` or
+/// `ReadExpect`.
+pub struct TaskContextResource<E: 'static = ()> {
+    pub task_context: SendWrapper<TaskContext<'static, E>>, // task context uses Rc
+}
+
+/// Insert <task_context> into <world> as a `TaskContextResource`
+pub fn insert_task_context<E: 'static>(world: &mut World, task_context: TaskContext<'static, E>) {
+    world.insert(TaskContextResource {
+        task_context: SendWrapper::new(task_context),
+    })
+}
+
+/// Run the world's tasks until they are all waiting
+///
+/// Call this once per frame, before running the `DrainTaskEvents` systems.
+pub async fn run_until_stalled<E: 'static>(world: &World) {
+    let mut task_context = world
+        .system_data::<ReadExpect<TaskContextResource<E>>>()
+        .task_context
+        .clone();
+    task_context.run_until_stalled().await
+}
+
+/// The events of type `T` published by tasks since the last frame
+pub struct TaskEvents<T> {
+    events: Vec<T>,
+}
+
+impl<T> Default for TaskEvents<T> {
+    fn default() -> Self {
+        TaskEvents { events: Vec::new() }
+    }
+}
+
+impl<T> TaskEvents<T> {
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.events.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// Replaces the `TaskEvents<T>` resource with the events of type `T`
+/// published since it last ran
+///
+/// The resource has to be inserted first.
+pub struct DrainTaskEvents<T> {
+    subscriber: SendWrapper<Subscriber<T>>, // subscribers use Rc
+}
+
+impl<T: 'static> DrainTaskEvents<T> {
+    pub fn new<E>(task_context: &TaskContext<'_, E>) -> Self {
+        DrainTaskEvents {
+            subscriber: SendWrapper::new(task_context.subscribe()),
+        }
+    }
+}
+
+impl<'a, T> System<'a> for DrainTaskEvents<T>
+where
+    T: Send + Sync + 'static,
+{
+    type SystemData = WriteExpect<'a, TaskEvents<T>>;
+
+    fn run(&mut self, mut task_events: Self::SystemData) {
+        task_events.events = self.subscriber.drain();
+    }
+}
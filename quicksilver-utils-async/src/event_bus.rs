@@ -0,0 +1,306 @@
+//! # event_bus
+//!
+//! Typed publish/subscribe channels, one per event type. Network,
+//! timer and UI tasks can each publish their own event types, and
+//! each subscriber gets its own copy of every event published after
+//! it subscribed.
+//!
+//! Channels are unbounded unless given a capacity with
+//! `EventBus::set_bounds`, in which case the `OverflowPolicy` decides
+//! what happens when a subscriber falls behind.
+
+use futures_util::{
+    future::poll_fn,
+    stream::{Stream, StreamExt},
+};
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+
+/// What to do with a new event when a subscriber's queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Evict the subscriber's oldest event to make room
+    DropOldest,
+    /// Discard the new event for that subscriber
+    DropNewest,
+    /// Refuse the event, `publish` fails and `send` waits for room
+    Backpressure,
+}
+
+/// The event handed back when a `Backpressure` channel is full
+#[derive(Debug, PartialEq, Eq)]
+pub struct Full<T>(pub T);
+
+struct SubscriberQueue<T> {
+    events: VecDeque<T>,
+    waker: Option<Waker>,
+}
+
+struct Channel<T> {
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    subscribers: Vec<Weak<RefCell<SubscriberQueue<T>>>>,
+    blocked_publishers: Vec<Waker>,
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Channel {
+            capacity: None,
+            policy: OverflowPolicy::DropOldest,
+            subscribers: Vec::new(),
+            blocked_publishers: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> Channel<T> {
+    fn is_blocked(&self) -> bool {
+        match (self.policy, self.capacity) {
+            (OverflowPolicy::Backpressure, Some(capacity)) => self
+                .subscribers
+                .iter()
+                .filter_map(Weak::upgrade)
+                .any(|queue| queue.borrow().events.len() >= capacity),
+            _ => false,
+        }
+    }
+
+    fn deliver(&mut self, event: T) {
+        self.subscribers.retain(|queue| queue.strong_count() > 0);
+        for queue in self.subscribers.iter().filter_map(Weak::upgrade) {
+            let queue: &mut SubscriberQueue<T> = &mut queue.borrow_mut();
+            if let Some(capacity) = self.capacity {
+                if queue.events.len() >= capacity {
+                    match self.policy {
+                        OverflowPolicy::DropOldest => {
+                            queue.events.pop_front();
+                        }
+                        OverflowPolicy::DropNewest => continue,
+                        OverflowPolicy::Backpressure => unreachable!("checked by is_blocked"),
+                    }
+                }
+            }
+            queue.events.push_back(event.clone());
+            if let Some(waker) = queue.waker.take() {
+                waker.wake()
+            }
+        }
+    }
+}
+
+/// A set of typed channels shared by every clone of the bus
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::event_bus::{EventBus, OverflowPolicy};
+/// #[derive(Clone, Debug)]
+/// struct Ticked;
+///
+/// let bus = EventBus::new();
+/// bus.set_bounds::<Ticked>(2, OverflowPolicy::DropOldest);
+/// let ticks = bus.subscribe::<Ticked>();
+///
+/// bus.publish(Ticked).unwrap();
+/// for tick in ticks.drain() {
+///     println!("{:?}", tick);
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct EventBus {
+    channels: Rc<RefCell<HashMap<TypeId, Rc<dyn Any>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    fn channel<T: 'static>(&self) -> Rc<RefCell<Channel<T>>> {
+        let channel = self
+            .channels
+            .borrow_mut()
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Rc::new(RefCell::new(Channel::<T>::default())))
+            .clone();
+        channel
+            .downcast::<RefCell<Channel<T>>>()
+            .expect("channels are keyed by their event type")
+    }
+
+    /// Bound every subscriber queue for events of type `T` to <capacity>
+    ///
+    /// Panics if <capacity> is zero, no queue could ever hold an event.
+    pub fn set_bounds<T: 'static>(&self, capacity: usize, policy: OverflowPolicy) {
+        assert!(capacity > 0, "event bus capacity must be non-zero");
+        let channel = self.channel::<T>();
+        let channel: &mut Channel<T> = &mut channel.borrow_mut();
+        channel.capacity = Some(capacity);
+        channel.policy = policy;
+    }
+
+    /// Start receiving events of type `T` published from now on
+    pub fn subscribe<T: 'static>(&self) -> Subscriber<T> {
+        let queue = Rc::new(RefCell::new(SubscriberQueue {
+            events: VecDeque::new(),
+            waker: None,
+        }));
+        let channel = self.channel::<T>();
+        channel.borrow_mut().subscribers.push(Rc::downgrade(&queue));
+        Subscriber { queue, channel }
+    }
+
+    /// Deliver <event> to every current subscriber of its type
+    ///
+    /// Only fails on a full `Backpressure` channel, in which case no
+    /// subscriber receives the event.
+    pub fn publish<T: Clone + 'static>(&self, event: T) -> Result<(), Full<T>> {
+        let channel = self.channel::<T>();
+        let channel: &mut Channel<T> = &mut channel.borrow_mut();
+        if channel.is_blocked() {
+            return Err(Full(event));
+        }
+        channel.deliver(event);
+        Ok(())
+    }
+
+    /// Deliver <event>, waiting for room on a full `Backpressure` channel
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::event_bus::EventBus;
+    /// # async fn example(bus: EventBus) {
+    /// for score in 0..3u32 {
+    ///     bus.send(score).await
+    /// }
+    /// # }
+    /// ```
+    pub async fn send<T: Clone + 'static>(&self, event: T) {
+        let channel = self.channel::<T>();
+        let mut event = Some(event);
+        poll_fn(move |cx| {
+            let channel: &mut Channel<T> = &mut channel.borrow_mut();
+            if channel.is_blocked() {
+                let waker = cx.waker();
+                if !channel
+                    .blocked_publishers
+                    .iter()
+                    .any(|w| w.will_wake(waker))
+                {
+                    channel.blocked_publishers.push(waker.clone());
+                }
+                Poll::Pending
+            } else {
+                channel.deliver(event.take().expect("polled after completion"));
+                Poll::Ready(())
+            }
+        })
+        .await
+    }
+}
+
+/// One subscriber's queue of events of type `T`
+///
+/// Also a `Stream` of events, which never ends. Dropping a subscriber
+/// unsubscribes it, which also releases any `send` waiting for room in
+/// its queue.
+///
+/// ```no_run
+/// # use quicksilver_utils_async::event_bus::EventBus;
+/// # use futures_util::stream::StreamExt;
+/// # async fn example(bus: EventBus) {
+/// let mut scores = bus.subscribe::<u32>();
+/// while let Some(score) = scores.next().await {
+///     println!("scored {}", score);
+/// }
+/// # }
+/// ```
+pub struct Subscriber<T> {
+    queue: Rc<RefCell<SubscriberQueue<T>>>,
+    channel: Rc<RefCell<Channel<T>>>,
+}
+
+impl<T> Subscriber<T> {
+    fn popped(&self) {
+        let blocked: Vec<Waker> = self
+            .channel
+            .borrow_mut()
+            .blocked_publishers
+            .drain(..)
+            .collect();
+        for waker in blocked {
+            waker.wake()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.borrow().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take the oldest queued event, if any
+    pub fn try_recv(&self) -> Option<T> {
+        let event = self.queue.borrow_mut().events.pop_front();
+        if event.is_some() {
+            self.popped();
+        }
+        event
+    }
+
+    /// Take every queued event
+    pub fn drain(&self) -> Vec<T> {
+        let events: Vec<T> = self.queue.borrow_mut().events.drain(..).collect();
+        self.popped();
+        events
+    }
+
+    /// Take at most <max> of the oldest queued events, leaving the rest
+    /// for a later frame
+    pub fn drain_up_to(&self, max: usize) -> Vec<T> {
+        let events: Vec<T> = {
+            let queue: &mut SubscriberQueue<T> = &mut self.queue.borrow_mut();
+            let count = max.min(queue.events.len());
+            queue.events.drain(..count).collect()
+        };
+        self.popped();
+        events
+    }
+
+    /// Wait for the next event
+    pub async fn recv(&mut self) -> T {
+        self.next().await.expect("subscriptions never end")
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        // publishers blocked on this queue can go ahead once it's gone
+        self.popped()
+    }
+}
+
+impl<T> Stream for Subscriber<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let event = {
+            let queue: &mut SubscriberQueue<T> = &mut self.queue.borrow_mut();
+            match queue.events.pop_front() {
+                Some(event) => event,
+                None => {
+                    queue.waker.replace(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        };
+        self.popped();
+        Poll::Ready(Some(event))
+    }
+}
@@ -0,0 +1,206 @@
+//! # frame_scheduler
+//!
+//! A governor for the main game loop. Game logic runs at a fixed
+//! timestep, driven by an accumulator of real elapsed time, while
+//! rendering happens at most once per frame and can be capped to a
+//! maximum fps. The leftover fraction of a timestep is handed to the
+//! renderer as an interpolation alpha.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::time::{sleep_until, Instant};
+
+const STATS_WINDOW: usize = 60;
+
+/// What the game loop should do this frame
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    updates: u32,
+    timestep: Duration,
+    delta: Duration,
+    alpha: f32,
+}
+
+impl Frame {
+    /// The number of fixed-timestep updates to run before rendering
+    pub fn updates(&self) -> u32 {
+        self.updates
+    }
+
+    /// The simulated time covered by each update
+    pub fn timestep(&self) -> Duration {
+        self.timestep
+    }
+
+    /// The real time since the previous frame started
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    /// How far between the last update and the next one the rendered
+    /// state should be, in `[0, 1)`
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+}
+
+/// Frame time statistics over the most recent frames
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    frame_count: u64,
+    dropped_updates: u64,
+    window: VecDeque<Duration>,
+}
+
+impl FrameStats {
+    fn record(&mut self, frame_time: Duration) {
+        self.frame_count += 1;
+        if self.window.len() == STATS_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(frame_time);
+    }
+
+    /// Total number of frames scheduled so far
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Updates thrown away because the game fell too far behind
+    pub fn dropped_updates(&self) -> u64 {
+        self.dropped_updates
+    }
+
+    pub fn last_frame_time(&self) -> Option<Duration> {
+        self.window.back().cloned()
+    }
+
+    pub fn average_frame_time(&self) -> Option<Duration> {
+        if self.window.is_empty() {
+            return None;
+        }
+        let total: Duration = self.window.iter().sum();
+        Some(total / self.window.len() as u32)
+    }
+
+    pub fn min_frame_time(&self) -> Option<Duration> {
+        self.window.iter().min().cloned()
+    }
+
+    pub fn max_frame_time(&self) -> Option<Duration> {
+        self.window.iter().max().cloned()
+    }
+
+    /// Frames per second, averaged over the recent frames
+    pub fn fps(&self) -> Option<f32> {
+        self.average_frame_time()
+            .filter(|average| *average > Duration::from_secs(0))
+            .map(|average| 1. / average.as_secs_f32())
+    }
+}
+
+/// Paces the update/render loop
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::frame_scheduler::FrameScheduler;
+/// # fn update() {}
+/// # fn render(_alpha: f32) {}
+/// async fn game_loop() {
+///     let mut scheduler = FrameScheduler::new(60);
+///     scheduler.set_max_fps(Some(144));
+///     loop {
+///         let frame = scheduler.next_frame().await;
+///         for _ in 0..frame.updates() {
+///             update()
+///         }
+///         render(frame.alpha())
+///     }
+/// }
+/// ```
+pub struct FrameScheduler {
+    timestep: Duration,
+    min_frame_time: Option<Duration>,
+    max_updates_per_frame: u32,
+    accumulator: Duration,
+    last_frame: Option<Instant>,
+    stats: FrameStats,
+}
+
+impl FrameScheduler {
+    /// Schedule <updates_per_second> fixed-timestep updates, with an uncapped render rate
+    pub fn new(updates_per_second: u32) -> Self {
+        assert!(updates_per_second > 0, "update rate must be non-zero");
+        FrameScheduler {
+            timestep: Duration::from_secs(1) / updates_per_second,
+            min_frame_time: None,
+            max_updates_per_frame: 10,
+            accumulator: Duration::from_secs(0),
+            last_frame: None,
+            stats: FrameStats::default(),
+        }
+    }
+
+    pub fn timestep(&self) -> Duration {
+        self.timestep
+    }
+
+    /// Cap the render rate, `None` renders as fast as the loop allows
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        self.min_frame_time = max_fps.map(|fps| {
+            assert!(fps > 0, "max fps must be non-zero");
+            Duration::from_secs(1) / fps
+        })
+    }
+
+    /// Limit how many updates one frame may catch up on; any backlog
+    /// beyond that is dropped instead of spiralling into ever longer
+    /// frames
+    pub fn set_max_updates_per_frame(&mut self, max_updates: u32) {
+        self.max_updates_per_frame = max_updates
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    /// Wait until the next frame may start, and work out how many
+    /// updates it needs to run
+    pub async fn next_frame(&mut self) -> Frame {
+        if let (Some(last_frame), Some(min_frame_time)) = (self.last_frame, self.min_frame_time) {
+            sleep_until(last_frame + min_frame_time).await;
+        }
+
+        let now = Instant::now();
+        let delta = match self.last_frame {
+            Some(last_frame) => now - last_frame,
+            None => Duration::from_secs(0),
+        };
+        self.last_frame = Some(now);
+        if delta > Duration::from_secs(0) {
+            self.stats.record(delta);
+        }
+
+        self.accumulator += delta;
+        let mut updates = 0;
+        while self.accumulator >= self.timestep {
+            if updates == self.max_updates_per_frame {
+                let behind = self.accumulator.as_nanos() / self.timestep.as_nanos();
+                self.stats.dropped_updates += behind as u64;
+                self.accumulator -= self.timestep * behind as u32;
+                break;
+            }
+            self.accumulator -= self.timestep;
+            updates += 1;
+        }
+
+        Frame {
+            updates,
+            timestep: self.timestep,
+            delta,
+            alpha: self.accumulator.as_secs_f32() / self.timestep.as_secs_f32(),
+        }
+    }
+}
@@ -25,6 +25,12 @@ mod std_web;
 #[cfg(all(target_arch = "wasm32", feature = "web-sys"))]
 mod web_sys;
 
+mod backoff;
+pub mod codec;
+#[cfg(feature = "specs")]
+pub mod ecs;
+pub mod event_bus;
+pub mod frame_scheduler;
 pub mod request;
 pub mod task_context;
 pub mod time;
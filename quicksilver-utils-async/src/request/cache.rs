@@ -0,0 +1,587 @@
+//! # cache
+//!
+//! An HTTP cache for GET requests, for config files and asset manifests
+//! that are fetched over and over.
+//!
+//! Responses are fresh for the `max-age` of their `Cache-Control`
+//! header, and a fresh response is served without touching the
+//! network. Once stale, a response with an `ETag` is revalidated with
+//! `If-None-Match`, and a `304 Not Modified` serves it again with the
+//! headers of the 304 merged in.
+//!
+//! A cached response is only used for requests with the same values
+//! of the headers named by its `Vary`. Responses to requests with an
+//! `Authorization` header are only cached when marked `public`.
+//!
+//! Recently used responses are kept in memory. A `CacheStorage` also
+//! keeps the ones with an `ETag` across runs, in a directory on desktop
+//! (`DirectoryStorage`) or in the browser's localStorage on the web
+//! (`LocalStorage`). They are stored with their expiry in wall-clock
+//! time, so a response that is still fresh after a restart is used
+//! without revalidating it.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use quicksilver_utils_async::request::*;
+//! # async fn example() -> Result<()> {
+//! let client = CachingClient::wrap(ServiceClientImpl::new());
+//! let uri: http::Uri = "https://example.com/manifest.json".parse().unwrap();
+//! let manifest = client.get(uri.clone()).send().await?;
+//! // fresh for the max-age it was sent with, so not fetched again
+//! let same_manifest = client.get(uri).send().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures_util::future::ready;
+use futures_util::stream::{self, StreamExt};
+use http::header::{
+    HeaderMap, HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, ETAG,
+    IF_NONE_MATCH, VARY,
+};
+use http::{Method, StatusCode};
+use log::{debug, trace};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{Request, Response, Result, ServiceClient, StreamingResponse};
+use crate::time::{unix_time, Instant};
+
+/// Keeps cached responses across runs
+///
+/// Failing to load or store an entry only loses the cached copy, so
+/// implementations log errors rather than returning them.
+pub trait CacheStorage {
+    fn load(&self, key: &str) -> Option<Vec<u8>>;
+
+    fn store(&self, key: &str, entry: &[u8]);
+
+    fn remove(&self, key: &str);
+}
+
+#[derive(Clone)]
+struct Entry {
+    /// The values of the request headers named by the response's `Vary`
+    varied: HeaderMap,
+    headers: HeaderMap,
+    body: Bytes,
+    /// `None` when the entry must be revalidated before use
+    fresh_until: Option<Instant>,
+}
+
+impl Entry {
+    /// The entry for a response with <headers> to a request with
+    /// <request_headers>, its body still to come, or `None` if the
+    /// response may not be cached
+    fn new(request_headers: &HeaderMap, headers: &HeaderMap) -> Option<Entry> {
+        if request_headers.contains_key(AUTHORIZATION) && !has_directive(headers, "public") {
+            return None;
+        }
+        let valid_for = freshness(headers)?;
+        let mut varied = HeaderMap::new();
+        for name in vary(headers)? {
+            for value in request_headers.get_all(&name) {
+                varied.append(name.clone(), value.clone());
+            }
+        }
+        Some(Entry {
+            varied,
+            headers: headers.clone(),
+            body: Bytes::new(),
+            fresh_until: Some(Instant::now() + valid_for),
+        })
+    }
+
+    /// Whether this entry may answer a request with <request_headers>
+    fn matches(&self, request_headers: &HeaderMap) -> bool {
+        vary(&self.headers).is_some_and(|names| {
+            names.iter().all(|name| {
+                request_headers
+                    .get_all(name)
+                    .iter()
+                    .eq(self.varied.get_all(name).iter())
+            })
+        })
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.fresh_until
+            .is_some_and(|fresh_until| Instant::now() < fresh_until)
+    }
+
+    /// Update the entry with the <headers> of a `304 Not Modified`,
+    /// false if it may no longer be cached
+    fn revalidate(&mut self, headers: &HeaderMap) -> bool {
+        // an age from before revalidating no longer applies
+        self.headers.remove(AGE);
+        for name in headers.keys().filter(|name| *name != CONTENT_LENGTH) {
+            self.headers.remove(name);
+            for value in headers.get_all(name) {
+                self.headers.append(name.clone(), value.clone());
+            }
+        }
+        match freshness(&self.headers) {
+            Some(valid_for) => {
+                self.fresh_until = Some(Instant::now() + valid_for);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn response(&self) -> Response {
+        let mut response = Response::new(self.body.clone());
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+
+    /// `<key>\r\n`, the expiry in milliseconds since the Unix epoch and
+    /// `\r\n`, the varied request headers and then the response headers
+    /// as in HTTP/1 each followed by a blank line, and the body
+    fn encode(&self, key: &str) -> Vec<u8> {
+        let expires = self.fresh_until.map_or(0, |fresh_until| {
+            (unix_time() + fresh_until.duration_since(Instant::now())).as_millis()
+        });
+        let mut encoded = format!("{}\r\n{}\r\n", key, expires).into_bytes();
+        for headers in &[&self.varied, &self.headers] {
+            for (name, value) in headers.iter() {
+                encoded.extend_from_slice(name.as_str().as_bytes());
+                encoded.extend_from_slice(b": ");
+                encoded.extend_from_slice(value.as_bytes());
+                encoded.extend_from_slice(b"\r\n");
+            }
+            encoded.extend_from_slice(b"\r\n");
+        }
+        encoded.extend_from_slice(&self.body);
+        encoded
+    }
+
+    /// The entry for <key> in <encoded>, `None` if it is corrupt or for
+    /// another key
+    fn decode(key: &str, encoded: &[u8]) -> Option<Entry> {
+        let mut rest = encoded;
+        let mut next_line = || {
+            let end = rest.windows(2).position(|window| window == b"\r\n")?;
+            let line = &rest[..end];
+            rest = &rest[end + 2..];
+            Some(line)
+        };
+
+        if next_line()? != key.as_bytes() {
+            return None;
+        }
+        let expires: u64 = std::str::from_utf8(next_line()?).ok()?.parse().ok()?;
+        let expires = Duration::from_millis(expires);
+        let mut header_blocks = [HeaderMap::new(), HeaderMap::new()];
+        for headers in header_blocks.iter_mut() {
+            loop {
+                let line = std::str::from_utf8(next_line()?).ok()?;
+                if line.is_empty() {
+                    break;
+                }
+                let mut field = line.splitn(2, ": ");
+                let name = HeaderName::from_bytes(field.next()?.as_bytes()).ok()?;
+                let value = HeaderValue::from_str(field.next()?).ok()?;
+                headers.append(name, value);
+            }
+        }
+        let [varied, headers] = header_blocks;
+        let now = unix_time();
+        Some(Entry {
+            varied,
+            headers,
+            body: Bytes::copy_from_slice(rest),
+            fresh_until: if expires > now {
+                Some(Instant::now() + (expires - now))
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Whether the `Cache-Control` of <headers> has <directive>, which must
+/// be lowercase
+fn has_directive(headers: &HeaderMap, directive: &str) -> bool {
+    headers.get_all(CACHE_CONTROL).iter().any(|directives| {
+        directives
+            .to_str()
+            .unwrap_or_default()
+            .split(',')
+            .any(|found| found.trim().eq_ignore_ascii_case(directive))
+    })
+}
+
+/// The request headers named by the `Vary` of <headers>, `None` for
+/// `Vary: *` which no later request can match
+fn vary(headers: &HeaderMap) -> Option<Vec<HeaderName>> {
+    let mut names = Vec::new();
+    for value in headers.get_all(VARY) {
+        for name in value.to_str().unwrap_or_default().split(',') {
+            let name = name.trim();
+            if name == "*" {
+                return None;
+            }
+            if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+                names.push(name);
+            }
+        }
+    }
+    Some(names)
+}
+
+/// How long <headers> allow a response to be used without revalidating,
+/// `None` if it may not be cached at all
+fn freshness(headers: &HeaderMap) -> Option<Duration> {
+    let mut max_age: Option<u64> = None;
+    for directives in headers.get_all(CACHE_CONTROL) {
+        for directive in directives.to_str().unwrap_or_default().split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+            if directive == "no-store" {
+                return None;
+            } else if directive == "no-cache" {
+                max_age = Some(0);
+            } else if let Some(seconds) = directive.strip_prefix("max-age=") {
+                max_age = max_age.or_else(|| seconds.parse().ok());
+            }
+        }
+    }
+    let age: u64 = headers
+        .get(AGE)
+        .and_then(|age| age.to_str().ok()?.parse().ok())
+        .unwrap_or(0);
+    let max_age = max_age.unwrap_or(0).saturating_sub(age);
+
+    // without either there is nothing to gain from keeping it
+    if max_age == 0 && !headers.contains_key(ETAG) {
+        return None;
+    }
+    Some(Duration::from_secs(max_age))
+}
+
+/// The most recently used entries, up to a fixed count
+struct Lru {
+    capacity: usize,
+    entries: HashMap<String, Entry>,
+    order: VecDeque<String>,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.get(key)?.clone();
+        self.touch(key);
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: &str, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key.to_string(), entry).is_some() {
+            self.touch(key);
+            return;
+        }
+        self.order.push_back(key.to_string());
+        while self.order.len() > self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                trace!("Evicting {} from the cache", evicted);
+                self.entries.remove(&evicted);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if self.entries.remove(key).is_some() {
+            self.order.retain(|cached| cached != key);
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(position) = self.order.iter().position(|cached| cached == key) {
+            let key = self.order.remove(position).expect("position is in range");
+            self.order.push_back(key);
+        }
+    }
+}
+
+/// The entries of a `CachingClient`, shared with the response bodies
+/// it is still streaming into the cache
+struct Cache {
+    memory: RefCell<Lru>,
+    storage: Option<Box<dyn CacheStorage>>,
+}
+
+impl Cache {
+    /// Forget the response cached for <key>, in memory and in storage
+    fn forget(&self, key: &str) {
+        self.memory.borrow_mut().remove(key);
+        if let Some(storage) = &self.storage {
+            storage.remove(key);
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Option<Entry> {
+        if let Some(entry) = self.memory.borrow_mut().get(key) {
+            return Some(entry);
+        }
+        let entry = Entry::decode(key, &self.storage.as_ref()?.load(key)?)?;
+        self.memory.borrow_mut().insert(key, entry.clone());
+        Some(entry)
+    }
+
+    fn remember(&self, key: &str, entry: Entry) {
+        if let Some(storage) = &self.storage {
+            // without an ETag it couldn't be revalidated once stale
+            if entry.headers.contains_key(ETAG) {
+                storage.store(key, &entry.encode(key));
+            } else {
+                storage.remove(key);
+            }
+        }
+        self.memory.borrow_mut().insert(key, entry);
+    }
+
+    /// The entry cached for <request>, which is made to ask whether a
+    /// stale entry has changed
+    fn prepare(&self, key: &str, request: &mut Request) -> Option<Entry> {
+        let entry = self
+            .lookup(key)
+            .filter(|entry| entry.matches(request.headers()))?;
+        if entry.is_fresh() {
+            trace!("Cache hit for {}", key);
+        } else if let Some(etag) = entry.headers.get(ETAG) {
+            if !request.headers().contains_key(IF_NONE_MATCH) {
+                request.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+            }
+        }
+        Some(entry)
+    }
+
+    /// The cached response made current by a 304 with <headers>
+    fn revalidated(&self, key: &str, mut entry: Entry, headers: &HeaderMap) -> Response {
+        debug!("Revalidated {}", key);
+        let cacheable = entry.revalidate(headers);
+        let response = entry.response();
+        if cacheable {
+            self.remember(key, entry);
+        } else {
+            self.forget(key);
+        }
+        response
+    }
+}
+
+/// The number of responses `CachingClient::wrap` keeps in memory
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// Wraps another client, caching the responses to GET requests
+///
+/// Other requests pass straight through, and a successful PUT, POST,
+/// PATCH or DELETE drops the cached response for its URI.
+///
+/// ```no_run
+/// # use quicksilver_utils_async::request::*;
+/// let client = CachingClient::wrap(ServiceClientImpl::new()).with_capacity(16);
+/// ```
+pub struct CachingClient<C> {
+    inner: C,
+    cache: Rc<Cache>,
+}
+
+impl<C: ServiceClient> CachingClient<C> {
+    /// Cache up to `DEFAULT_CAPACITY` responses in memory
+    pub fn wrap(inner: C) -> Self {
+        CachingClient {
+            inner,
+            cache: Rc::new(Cache {
+                memory: RefCell::new(Lru {
+                    capacity: DEFAULT_CAPACITY,
+                    entries: HashMap::new(),
+                    order: VecDeque::new(),
+                }),
+                storage: None,
+            }),
+        }
+    }
+
+    /// Keep up to <capacity> responses in memory
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        self.cache.memory.borrow_mut().capacity = capacity;
+        self
+    }
+
+    /// Also keep responses with an `ETag` in <storage>, for later runs
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::request::*;
+    /// let client = CachingClient::wrap(ServiceClientImpl::new())
+    ///     .with_storage(DirectoryStorage::new(std::env::temp_dir().join("my-game")));
+    /// ```
+    pub fn with_storage<S: CacheStorage + 'static>(mut self, storage: S) -> Self {
+        Rc::get_mut(&mut self.cache)
+            .expect("no response is being cached while the client is set up")
+            .storage = Some(Box::new(storage));
+        self
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+/// The key of the cached response to a GET of <request>'s URI
+fn cache_key(request: &Request) -> String {
+    format!("{} {}", Method::GET, request.uri())
+}
+
+#[async_trait(?Send)]
+impl<C: ServiceClient> ServiceClient for CachingClient<C> {
+    fn new() -> Self {
+        CachingClient::wrap(C::new())
+    }
+
+    /// A GET that isn't answered from the cache streams its response,
+    /// which is cached once the whole body has been read
+    async fn send_streaming(&self, mut request: Request) -> Result<StreamingResponse> {
+        let key = cache_key(&request);
+        if request.method() != Method::GET {
+            let invalidates = invalidates(request.method());
+            let response = self.inner.send_streaming(request).await?;
+            if invalidates && response.status().is_success() {
+                self.cache.forget(&key);
+            }
+            return Ok(response);
+        }
+
+        let request_headers = request.headers().clone();
+        let cached = self.cache.prepare(&key, &mut request);
+        if let Some(entry) = cached.as_ref().filter(|entry| entry.is_fresh()) {
+            return Ok(buffered(entry.response()));
+        }
+        let response = self.inner.send_streaming(request).await?;
+        match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(entry)) => Ok(buffered(self.cache.revalidated(
+                &key,
+                entry,
+                response.headers(),
+            ))),
+            (StatusCode::OK, _) => match Entry::new(&request_headers, response.headers()) {
+                Some(entry) => {
+                    let (parts, body) = response.into_parts();
+                    let body = cache_when_read(self.cache.clone(), key, entry, body);
+                    Ok(StreamingResponse::from_parts(parts, body))
+                }
+                None => {
+                    self.cache.forget(&key);
+                    Ok(response)
+                }
+            },
+            _ => Ok(response),
+        }
+    }
+
+    async fn send(&self, mut request: Request) -> Result<Response> {
+        let key = cache_key(&request);
+        if request.method() != Method::GET {
+            let invalidates = invalidates(request.method());
+            let response = self.inner.send(request).await?;
+            if invalidates && response.status().is_success() {
+                self.cache.forget(&key);
+            }
+            return Ok(response);
+        }
+
+        let request_headers = request.headers().clone();
+        let cached = self.cache.prepare(&key, &mut request);
+        if let Some(entry) = cached.as_ref().filter(|entry| entry.is_fresh()) {
+            return Ok(entry.response());
+        }
+        let response = self.inner.send(request).await?;
+        match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(entry)) => {
+                Ok(self.cache.revalidated(&key, entry, response.headers()))
+            }
+            (StatusCode::OK, _) => {
+                match Entry::new(&request_headers, response.headers()) {
+                    Some(mut entry) => {
+                        entry.body = response.body().clone();
+                        self.cache.remember(&key, entry);
+                    }
+                    None => self.cache.forget(&key),
+                }
+                Ok(response)
+            }
+            _ => Ok(response),
+        }
+    }
+}
+
+/// <response> as a streaming response with its body in one chunk
+fn buffered(response: Response) -> StreamingResponse {
+    let (parts, body) = response.into_parts();
+    let body = stream::once(ready(Ok(body))).boxed_local();
+    StreamingResponse::from_parts(parts, body)
+}
+
+/// Pass <body> through, remembering <entry> with it once all of it has
+/// been read
+fn cache_when_read(
+    cache: Rc<Cache>,
+    key: String,
+    entry: Entry,
+    body: super::BodyStream,
+) -> super::BodyStream {
+    let pending = Some((cache, key, entry, BytesMut::new()));
+    stream::unfold((body, pending), |(mut body, mut pending)| async move {
+        match body.next().await {
+            Some(Ok(chunk)) => {
+                if let Some((_, _, _, collected)) = &mut pending {
+                    collected.extend_from_slice(&chunk);
+                }
+                Some((Ok(chunk), (body, pending)))
+            }
+            // a partial body is not worth keeping
+            Some(Err(e)) => Some((Err(e), (body, None))),
+            None => {
+                if let Some((cache, key, mut entry, collected)) = pending {
+                    entry.body = collected.freeze();
+                    cache.remember(&key, entry);
+                }
+                None
+            }
+        }
+    })
+    .boxed_local()
+}
+
+/// <bytes> as lowercase hex, for storage that only holds strings
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The bytes encoded by `to_hex`, `None` if <hex> isn't valid
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Whether a successful <method> request changes the resource
+fn invalidates(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::PUT | Method::POST | Method::DELETE | Method::PATCH
+    )
+}
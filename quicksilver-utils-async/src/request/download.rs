@@ -0,0 +1,93 @@
+use bytes::BytesMut;
+use futures_util::future::ready;
+use futures_util::stream::{self, LocalBoxStream, StreamExt};
+use http::header::CONTENT_LENGTH;
+
+use super::{BodyStream, Response, Result, StreamingResponse};
+
+/// How much of a download has arrived
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes of the body received so far
+    pub received: u64,
+    /// The size of the body, when the server sent a `Content-Length`
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    /// The fraction received, in `[0, 1]`, if the total is known
+    ///
+    /// A compressed body can be larger than its `Content-Length`, so the
+    /// fraction is capped at 1.
+    pub fn fraction(&self) -> Option<f32> {
+        match self.total {
+            Some(0) => Some(1.),
+            Some(total) => Some((self.received as f64 / total as f64).min(1.) as f32),
+            None => None,
+        }
+    }
+}
+
+/// What happened next in a download, see `RequestBuilder::download`
+#[derive(Debug)]
+pub enum DownloadEvent {
+    /// More of the body arrived
+    Progress(Progress),
+    /// The whole body arrived, always the last event
+    Finished(Response),
+}
+
+/// The events of a download, ending after `DownloadEvent::Finished` or
+/// an error
+pub type Download = LocalBoxStream<'static, Result<DownloadEvent>>;
+
+struct State {
+    response: Option<http::response::Parts>,
+    body: BodyStream,
+    collected: BytesMut,
+    total: Option<u64>,
+}
+
+/// Report the progress of reading <response>'s body, starting with
+/// nothing received
+pub(crate) fn download(response: StreamingResponse) -> Download {
+    let (parts, body) = response.into_parts();
+    let total = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse().ok());
+    let started = Progress { received: 0, total };
+    let state = State {
+        response: Some(parts),
+        body,
+        collected: BytesMut::new(),
+        total,
+    };
+
+    let rest = stream::unfold(state, |mut state| async move {
+        let parts = state.response.take()?;
+        loop {
+            match state.body.next().await {
+                Some(Ok(chunk)) if chunk.is_empty() => continue,
+                Some(Ok(chunk)) => {
+                    state.collected.extend_from_slice(&chunk);
+                    let progress = Progress {
+                        received: state.collected.len() as u64,
+                        total: state.total,
+                    };
+                    state.response = Some(parts);
+                    return Some((Ok(DownloadEvent::Progress(progress)), state));
+                }
+                Some(Err(e)) => return Some((Err(e), state)),
+                None => {
+                    let body = std::mem::take(&mut state.collected).freeze();
+                    let response = Response::from_parts(parts, body);
+                    return Some((Ok(DownloadEvent::Finished(response)), state));
+                }
+            }
+        }
+    });
+    stream::once(ready(Ok(DownloadEvent::Progress(started))))
+        .chain(rest)
+        .boxed_local()
+}
@@ -0,0 +1,131 @@
+use bytes::{Bytes, BytesMut};
+use http::header::HeaderValue;
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+
+use crate::time::Instant;
+
+/// A `multipart/form-data` body, for uploading files along with form
+/// fields, see `RequestBuilder::multipart`
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::request::*;
+/// # use http::Method;
+/// # async fn example(client: ServiceClientImpl, replay: Vec<u8>) -> Result<()> {
+/// let form = Multipart::new()
+///     .text("level", "1-1")
+///     .file("replay", "run.replay", "application/octet-stream", replay);
+/// client
+///     .request(Method::POST, "https://example.com/replays".parse().unwrap())
+///     .multipart(form)
+///     .send()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Multipart {
+    boundary: String,
+    body: BytesMut,
+    /// The first invalid part, reported when the request is built
+    pub(super) error: Option<http::Error>,
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        let random = RandomState::new().hash_one(Instant::now());
+        Multipart {
+            boundary: format!("------------------------{:016x}", random),
+            body: BytesMut::new(),
+            error: None,
+        }
+    }
+}
+
+impl Multipart {
+    pub fn new() -> Self {
+        Multipart::default()
+    }
+
+    /// Add a text field
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.start_part(name, None, None);
+        self.body.extend_from_slice(value.as_bytes());
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    /// Add a file called <file_name> with <data> of <content_type>
+    ///
+    /// A <content_type> that isn't a valid header value leaves the file
+    /// out, and fails the request the form is sent with.
+    pub fn file<V>(
+        mut self,
+        name: &str,
+        file_name: &str,
+        content_type: V,
+        data: impl Into<Bytes>,
+    ) -> Self
+    where
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        let content_type = match HeaderValue::try_from(content_type) {
+            Ok(content_type) => content_type,
+            Err(e) => {
+                self.error = self.error.or(Some(e.into()));
+                return self;
+            }
+        };
+        self.start_part(name, Some(file_name), Some(&content_type));
+        self.body.extend_from_slice(&data.into());
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    /// The `Content-Type` of this body, including its boundary
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// The encoded body, ending with the closing boundary
+    pub fn finish(mut self) -> Bytes {
+        self.body
+            .extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        self.body.freeze()
+    }
+
+    fn start_part(
+        &mut self,
+        name: &str,
+        file_name: Option<&str>,
+        content_type: Option<&HeaderValue>,
+    ) {
+        let mut headers = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+            self.boundary,
+            escape(name)
+        );
+        if let Some(file_name) = file_name {
+            headers.push_str(&format!("; filename=\"{}\"", escape(file_name)));
+        }
+        if content_type.is_some() {
+            headers.push_str("\r\nContent-Type: ");
+        }
+        self.body.extend_from_slice(headers.as_bytes());
+        if let Some(content_type) = content_type {
+            self.body.extend_from_slice(content_type.as_bytes());
+        }
+        self.body.extend_from_slice(b"\r\n\r\n");
+    }
+}
+
+// quoted names and file names escape like browsers do
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
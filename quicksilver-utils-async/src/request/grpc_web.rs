@@ -0,0 +1,314 @@
+//! # grpc_web
+//!
+//! A gRPC-web client for services defined in `.proto` files, on top of
+//! any `ServiceClient`, so it works wherever `ServiceClientImpl` does.
+//!
+//! Messages are sent as `application/grpc-web+proto`, each in a frame
+//! of a flag byte and a big-endian 4 byte length. The call's outcome
+//! comes from the `grpc-status` and `grpc-message` trailers, sent in a
+//! final frame or, when there are no messages, as response headers; a
+//! non-zero status is a `RequestError::Grpc`.
+//!
+//! Methods are addressed by the URI of `/<package>.<Service>/<Method>`
+//! on the server.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use quicksilver_utils_async::request::grpc_web::*;
+//! # use quicksilver_utils_async::request::*;
+//! # use protobuf::well_known_types::{StringValue, UInt32Value};
+//! # use futures_util::stream::StreamExt;
+//! # async fn example(from: UInt32Value) -> Result<()> {
+//! let client = GrpcWebClient::wrap(ServiceClientImpl::new());
+//! let mut countdown = client
+//!     .server_streaming::<_, StringValue>("https://example.com/demo.Rocket/Countdown".parse().unwrap(), &from)
+//!     .await?;
+//! while let Some(count) = countdown.next().await {
+//!     println!("{}", count?.get_value());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::stream::{self, LocalBoxStream, StreamExt};
+use http::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
+use http::{Method, Uri};
+use log::trace;
+
+use super::{error_for_status, BodyStream, RequestError, Response, Result, ServiceClient};
+use crate::codec::{Decode, Encode, Protobuf};
+
+/// The content type of gRPC-web requests and responses with binary
+/// protobuf messages
+pub const GRPC_WEB_PROTO: &str = "application/grpc-web+proto";
+
+const FRAME_HEADER_LEN: usize = 5;
+const TRAILERS_FLAG: u8 = 0x80;
+const COMPRESSED_FLAG: u8 = 0x01;
+
+/// The `grpc-status` of a successful call
+const OK: u32 = 0;
+
+/// Frame <message> as the body of a gRPC-web request or response
+pub fn message_frame<T: protobuf::Message>(message: &T) -> Result<Bytes> {
+    Ok(frame(0, &Protobuf::encode(message)?))
+}
+
+/// The final frame of a gRPC-web response, with the call's status
+pub fn trailers_frame(status: u32, message: &str) -> Bytes {
+    let mut trailers = format!("grpc-status: {}\r\n", status);
+    if !message.is_empty() {
+        trailers.push_str(&format!("grpc-message: {}\r\n", percent_encode(message)));
+    }
+    frame(TRAILERS_FLAG, trailers.as_bytes())
+}
+
+fn frame(flag: u8, payload: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.put_u8(flag);
+    frame.put_u32(payload.len() as u32);
+    frame.extend_from_slice(payload);
+    frame.freeze()
+}
+
+enum Frame {
+    Message(Bytes),
+    /// The status in the trailers, `Ok` if the call succeeded
+    Trailers(Result<()>),
+}
+
+/// Take the next complete frame off the front of <buffer>
+fn next_frame(buffer: &mut BytesMut) -> Option<Result<Frame>> {
+    if buffer.len() < FRAME_HEADER_LEN {
+        return None;
+    }
+    let len = (&buffer[1..FRAME_HEADER_LEN]).get_u32() as usize;
+    if buffer.len() < FRAME_HEADER_LEN + len {
+        return None;
+    }
+    let flag = buffer[0];
+    buffer.advance(FRAME_HEADER_LEN);
+    let payload = buffer.split_to(len).freeze();
+
+    if flag & COMPRESSED_FLAG != 0 {
+        return Some(Err(RequestError::Decode(
+            "Compressed grpc-web frames are not supported".to_string(),
+        )));
+    }
+    if flag & TRAILERS_FLAG != 0 {
+        let trailers = String::from_utf8_lossy(&payload);
+        let fields = trailers.split("\r\n").filter_map(|line| {
+            let mut field = line.splitn(2, ':');
+            Some((field.next()?.trim(), field.next()?.trim()))
+        });
+        let status = status(fields).unwrap_or_else(|| {
+            Err(RequestError::Decode(
+                "grpc-web trailers without grpc-status".to_string(),
+            ))
+        });
+        return Some(Ok(Frame::Trailers(status)));
+    }
+    Some(Ok(Frame::Message(payload)))
+}
+
+/// The call's status from its trailer <fields>, if they have one
+fn status<'a>(fields: impl Iterator<Item = (&'a str, &'a str)>) -> Option<Result<()>> {
+    let mut code = None;
+    let mut message = String::new();
+    for (name, value) in fields {
+        if name.eq_ignore_ascii_case("grpc-status") {
+            code = Some(
+                value
+                    .parse::<u32>()
+                    .map_err(|_| RequestError::Decode(format!("Invalid grpc-status {}", value))),
+            );
+        } else if name.eq_ignore_ascii_case("grpc-message") {
+            message = percent_decode(value);
+        }
+    }
+    Some(match code? {
+        Ok(OK) => Ok(()),
+        Ok(code) => Err(RequestError::Grpc { code, message }),
+        Err(e) => Err(e),
+    })
+}
+
+fn header_status(headers: &HeaderMap) -> Option<Result<()>> {
+    status(
+        headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?))),
+    )
+}
+
+fn frames(body: BodyStream) -> LocalBoxStream<'static, Result<Frame>> {
+    let state = (body, BytesMut::new(), false);
+    stream::unfold(state, |(mut body, mut buffer, failed)| async move {
+        if failed {
+            return None;
+        }
+        loop {
+            if let Some(frame) = next_frame(&mut buffer) {
+                let failed = frame.is_err();
+                return Some((frame, (body, buffer, failed)));
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(e), (body, buffer, true))),
+                None if buffer.is_empty() => return None,
+                None => {
+                    let truncated = RequestError::Decode("Truncated grpc-web frame".to_string());
+                    return Some((Err(truncated), (body, buffer, true)));
+                }
+            }
+        }
+    })
+    .boxed_local()
+}
+
+/// Calls gRPC-web methods through another client
+pub struct GrpcWebClient<C> {
+    inner: C,
+}
+
+impl<C: ServiceClient> GrpcWebClient<C> {
+    pub fn wrap(inner: C) -> Self {
+        GrpcWebClient { inner }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Call the method at <uri> with <request>, expecting exactly one
+    /// message back
+    pub async fn unary<RequestT, ResponseT>(
+        &self,
+        uri: Uri,
+        request: &RequestT,
+    ) -> Result<ResponseT>
+    where
+        RequestT: protobuf::Message,
+        ResponseT: protobuf::Message,
+    {
+        let mut messages = self
+            .server_streaming::<RequestT, ResponseT>(uri, request)
+            .await?;
+        let response = match messages.next().await {
+            Some(response) => response?,
+            None => {
+                return Err(RequestError::Decode(
+                    "No message in unary grpc-web response".to_string(),
+                ))
+            }
+        };
+        match messages.next().await {
+            None => Ok(response),
+            Some(Err(e)) => Err(e),
+            Some(Ok(_)) => Err(RequestError::Decode(
+                "More than one message in unary grpc-web response".to_string(),
+            )),
+        }
+    }
+
+    /// Call the method at <uri> with <request>, handing back its
+    /// messages as they arrive
+    ///
+    /// A failed call ends the stream with its error.
+    pub async fn server_streaming<RequestT, ResponseT>(
+        &self,
+        uri: Uri,
+        request: &RequestT,
+    ) -> Result<LocalBoxStream<'static, Result<ResponseT>>>
+    where
+        RequestT: protobuf::Message,
+        ResponseT: protobuf::Message,
+    {
+        let response = self
+            .inner
+            .request(Method::POST, uri)
+            .header(CONTENT_TYPE, GRPC_WEB_PROTO)
+            .header(ACCEPT, GRPC_WEB_PROTO)
+            .header("x-grpc-web", "1")
+            .body(message_frame(request)?)
+            .send_streaming()
+            .await?;
+        let (parts, mut body) = response.into_parts();
+
+        if !parts.status.is_success() {
+            let mut collected = BytesMut::new();
+            while let Some(chunk) = body.next().await {
+                collected.extend_from_slice(&chunk?);
+            }
+            error_for_status(Response::from_parts(parts, collected.freeze()))?;
+            unreachable!("the status is not a success");
+        }
+
+        // a trailers-only response, the call ended without messages
+        if let Some(status) = header_status(&parts.headers) {
+            status?;
+            return Ok(stream::empty().boxed_local());
+        }
+
+        let messages = stream::unfold(Some(frames(body)), |frames| async move {
+            let mut frames = frames?;
+            match frames.next().await {
+                Some(Ok(Frame::Message(payload))) => {
+                    trace!("grpc-web message bytes: {:?}", payload);
+                    Some((Protobuf::decode(&payload), Some(frames)))
+                }
+                Some(Ok(Frame::Trailers(Ok(())))) => None,
+                Some(Ok(Frame::Trailers(Err(e)))) | Some(Err(e)) => Some((Err(e), None)),
+                None => Some((
+                    Err(RequestError::Decode(
+                        "grpc-web response ended without grpc-status".to_string(),
+                    )),
+                    None,
+                )),
+            }
+        });
+        Ok(messages.boxed_local())
+    }
+}
+
+// grpc-message is percent-encoded, see the gRPC over HTTP2 spec
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (b' '..=b'~').contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(message: &str) -> String {
+    let bytes = message.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,60 @@
+use async_trait::async_trait;
+use futures_util::future::ready;
+use futures_util::stream::{self, StreamExt};
+use http::header::CONTENT_TYPE;
+
+use super::{HttpTransport, Request, Response, Result, StreamingResponse};
+
+type Handler = dyn Fn(Request) -> Result<Response>;
+
+/// Answers requests in memory, without touching the network
+///
+/// By default every request gets a 200 echoing its body and content
+/// type; `LoopbackTransport::new` answers with a handler instead. Useful
+/// for testing code built on a `ServiceClient` on any platform.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::request::*;
+/// let teapot = Client::with_transport(LoopbackTransport::new(|_request| {
+///     Ok(http::Response::builder().status(418).body("short and stout".into()).unwrap())
+/// }));
+/// ```
+pub struct LoopbackTransport {
+    handler: Box<Handler>,
+}
+
+impl Default for LoopbackTransport {
+    fn default() -> Self {
+        LoopbackTransport::new(|request| {
+            let mut response = http::Response::builder();
+            if let Some(content_type) = request.headers().get(CONTENT_TYPE) {
+                response = response.header(CONTENT_TYPE, content_type);
+            }
+            Ok(response
+                .body(request.into_body())
+                .expect("echoed headers are valid"))
+        })
+    }
+}
+
+impl LoopbackTransport {
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(Request) -> Result<Response> + 'static,
+    {
+        LoopbackTransport {
+            handler: Box::new(handler),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl HttpTransport for LoopbackTransport {
+    async fn send(&self, request: Request) -> Result<StreamingResponse> {
+        let (parts, body) = (self.handler)(request)?.into_parts();
+        let body = stream::once(ready(Ok(body))).boxed_local();
+        Ok(StreamingResponse::from_parts(parts, body))
+    }
+}
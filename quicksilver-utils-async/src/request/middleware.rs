@@ -0,0 +1,405 @@
+//! # middleware
+//!
+//! Layers around a `ServiceClient` that see every request on its way
+//! out and every response on its way back: authentication, logging,
+//! metrics, default headers, or anything else implementing
+//! `Middleware`.
+//!
+//! Middleware runs in the order it was added, so the first one added
+//! sees the request first and the response last.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use quicksilver_utils_async::request::middleware::*;
+//! # use quicksilver_utils_async::request::*;
+//! # fn example(token: String) {
+//! let metrics = LatencyMetrics::new();
+//! let client = MiddlewareClient::wrap(ServiceClientImpl::new())
+//!     .with(Logging::new())
+//!     .with(metrics.clone())
+//!     .with(BearerAuth::new(StaticToken(token)));
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use futures_util::future::LocalBoxFuture;
+use http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use http::StatusCode;
+use log::{debug, warn};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{clone_request, Request, RequestError, Result, ServiceClient, StreamingResponse};
+use crate::time::Instant;
+
+/// One layer of a `MiddlewareClient`
+#[async_trait(?Send)]
+pub trait Middleware {
+    /// Handle <request>, usually by passing it on with `next.run`
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<StreamingResponse>;
+}
+
+/// The rest of the chain after the current middleware
+///
+/// `run` may be called more than once, to replay a request.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middleware: &'a [Rc<dyn Middleware>],
+    send: &'a dyn Fn(Request) -> LocalBoxFuture<'a, Result<StreamingResponse>>,
+}
+
+impl<'a> Next<'a> {
+    pub async fn run(self, request: Request) -> Result<StreamingResponse> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => {
+                let next = Next {
+                    middleware: rest,
+                    send: self.send,
+                };
+                first.handle(request, next).await
+            }
+            None => (self.send)(request).await,
+        }
+    }
+}
+
+/// Wraps another client, passing every request through a chain of
+/// `Middleware`
+pub struct MiddlewareClient<C> {
+    inner: C,
+    middleware: Vec<Rc<dyn Middleware>>,
+}
+
+impl<C: ServiceClient> MiddlewareClient<C> {
+    pub fn wrap(inner: C) -> Self {
+        MiddlewareClient {
+            inner,
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Add <middleware> to the end of the chain
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Rc::new(middleware));
+        self
+    }
+
+    /// Send <auth_token> with every request, as `BearerAuth` with a
+    /// `StaticToken` does
+    ///
+    /// This replaces `ServiceClient::set_auth_token`, which sent the
+    /// token without the `Bearer ` prefix.
+    #[deprecated(note = "add `BearerAuth::new(StaticToken(token))` with `with` instead")]
+    pub fn set_auth_token(&mut self, auth_token: &str) {
+        self.middleware.push(Rc::new(BearerAuth::new(StaticToken(
+            auth_token.to_string(),
+        ))));
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+#[async_trait(?Send)]
+impl<C: ServiceClient> ServiceClient for MiddlewareClient<C> {
+    fn new() -> Self {
+        MiddlewareClient::wrap(C::new())
+    }
+
+    async fn send_streaming(&self, request: Request) -> Result<StreamingResponse> {
+        let send = |request| self.inner.send_streaming(request);
+        let next = Next {
+            middleware: &self.middleware,
+            send: &send,
+        };
+        next.run(request).await
+    }
+}
+
+/// A source of auth tokens for `BearerAuth`
+#[async_trait(?Send)]
+pub trait TokenProvider {
+    /// The token to use now, fetching one if there is none yet or it
+    /// has expired
+    async fn token(&self) -> Result<String>;
+
+    /// Get a new token, the current one was rejected
+    async fn refresh(&self) -> Result<String>;
+}
+
+/// A token that never changes
+pub struct StaticToken(pub String);
+
+#[async_trait(?Send)]
+impl TokenProvider for StaticToken {
+    async fn token(&self) -> Result<String> {
+        Ok(self.0.clone())
+    }
+
+    async fn refresh(&self) -> Result<String> {
+        Ok(self.0.clone())
+    }
+}
+
+type FetchToken = dyn Fn() -> LocalBoxFuture<'static, Result<(String, Option<Duration>)>>;
+
+/// Caches a token from an async fetch function until it expires or is
+/// rejected
+///
+/// The fetch function returns the token and, if known, how long it is
+/// valid for.
+pub struct CachedToken {
+    fetch: Box<FetchToken>,
+    cached: RefCell<Option<(String, Option<Instant>)>>,
+}
+
+impl CachedToken {
+    pub fn new<F>(fetch: F) -> Self
+    where
+        F: Fn() -> LocalBoxFuture<'static, Result<(String, Option<Duration>)>> + 'static,
+    {
+        CachedToken {
+            fetch: Box::new(fetch),
+            cached: RefCell::new(None),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl TokenProvider for CachedToken {
+    async fn token(&self) -> Result<String> {
+        if let Some((token, expires)) = &*self.cached.borrow() {
+            if expires.map_or(true, |expires| Instant::now() < expires) {
+                return Ok(token.clone());
+            }
+        }
+        self.refresh().await
+    }
+
+    async fn refresh(&self) -> Result<String> {
+        let (token, valid_for) = (self.fetch)().await?;
+        let expires = valid_for.map(|valid_for| Instant::now() + valid_for);
+        self.cached.replace(Some((token.clone(), expires)));
+        Ok(token)
+    }
+}
+
+/// Sends `Authorization: Bearer <token>`, and on a 401 refreshes the
+/// token and replays the request once
+pub struct BearerAuth<P> {
+    provider: P,
+}
+
+impl<P: TokenProvider> BearerAuth<P> {
+    pub fn new(provider: P) -> Self {
+        BearerAuth { provider }
+    }
+}
+
+fn authorize(mut request: Request, token: &str) -> Result<Request> {
+    let value = HeaderValue::from_str(&format!("Bearer {}", token))
+        .map_err(|e| RequestError::Encode(format!("Invalid auth token {}", e)))?;
+    request.headers_mut().insert(AUTHORIZATION, value);
+    Ok(request)
+}
+
+#[async_trait(?Send)]
+impl<P: TokenProvider> Middleware for BearerAuth<P> {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<StreamingResponse> {
+        let replay = clone_request(&request);
+        let token = self.provider.token().await?;
+        let response = next.run(authorize(request, &token)?).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        debug!("Refreshing rejected token for {}", replay.uri());
+        let token = self.provider.refresh().await?;
+        next.run(authorize(replay, &token)?).await
+    }
+}
+
+type OnRequest = dyn Fn(&Request);
+type OnResponse = dyn Fn(&Request, &Result<StreamingResponse>, Duration);
+
+/// Calls hooks with every request and its outcome, by default logging
+/// them with the `log` crate
+pub struct Logging {
+    on_request: Box<OnRequest>,
+    on_response: Box<OnResponse>,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging::with_hooks(
+            |request| debug!("--> {} {}", request.method(), request.uri()),
+            |request, response, latency| match response {
+                Ok(response) => debug!(
+                    "<-- {} {} {} in {:?}",
+                    request.method(),
+                    request.uri(),
+                    response.status(),
+                    latency
+                ),
+                Err(e) => warn!(
+                    "<-- {} {} failed in {:?}: {}",
+                    request.method(),
+                    request.uri(),
+                    latency,
+                    e
+                ),
+            },
+        )
+    }
+}
+
+impl Logging {
+    pub fn new() -> Self {
+        Logging::default()
+    }
+
+    /// Call <on_request> before each request is sent, and <on_response>
+    /// with its outcome and latency once the response headers arrive
+    pub fn with_hooks<Req, Resp>(on_request: Req, on_response: Resp) -> Self
+    where
+        Req: Fn(&Request) + 'static,
+        Resp: Fn(&Request, &Result<StreamingResponse>, Duration) + 'static,
+    {
+        Logging {
+            on_request: Box::new(on_request),
+            on_response: Box::new(on_response),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Middleware for Logging {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<StreamingResponse> {
+        (self.on_request)(&request);
+        let sent = clone_request(&request);
+        let start = Instant::now();
+        let response = next.run(request).await;
+        (self.on_response)(&sent, &response, start.elapsed());
+        response
+    }
+}
+
+/// Latency statistics gathered by `LatencyMetrics`
+///
+/// Latency is measured until the response headers arrive.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyStats {
+    request_count: u64,
+    failure_count: u64,
+    total: Duration,
+    last: Option<Duration>,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl LatencyStats {
+    fn record(&mut self, latency: Duration, failed: bool) {
+        self.request_count += 1;
+        if failed {
+            self.failure_count += 1;
+        }
+        self.total += latency;
+        self.last = Some(latency);
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+        self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+    }
+
+    pub fn request_count(&self) -> u64 {
+        self.request_count
+    }
+
+    /// Requests that failed or got a non-2xx response
+    pub fn failure_count(&self) -> u64 {
+        self.failure_count
+    }
+
+    pub fn last_latency(&self) -> Option<Duration> {
+        self.last
+    }
+
+    pub fn average_latency(&self) -> Option<Duration> {
+        if self.request_count == 0 {
+            return None;
+        }
+        Some(self.total / self.request_count as u32)
+    }
+
+    pub fn min_latency(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn max_latency(&self) -> Option<Duration> {
+        self.max
+    }
+}
+
+/// Records request latencies, shared between every clone
+#[derive(Clone, Default)]
+pub struct LatencyMetrics {
+    stats: Rc<RefCell<LatencyStats>>,
+}
+
+impl LatencyMetrics {
+    pub fn new() -> Self {
+        LatencyMetrics::default()
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        self.stats.borrow().clone()
+    }
+
+    pub fn reset(&self) {
+        self.stats.replace(LatencyStats::default());
+    }
+}
+
+#[async_trait(?Send)]
+impl Middleware for LatencyMetrics {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<StreamingResponse> {
+        let start = Instant::now();
+        let response = next.run(request).await;
+        let failed = match &response {
+            Ok(response) => !response.status().is_success(),
+            Err(_) => true,
+        };
+        self.stats.borrow_mut().record(start.elapsed(), failed);
+        response
+    }
+}
+
+/// Adds headers to every request that doesn't already set them
+pub struct InjectHeaders {
+    headers: HeaderMap,
+}
+
+impl InjectHeaders {
+    pub fn new(headers: HeaderMap) -> Self {
+        InjectHeaders { headers }
+    }
+}
+
+#[async_trait(?Send)]
+impl Middleware for InjectHeaders {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> Result<StreamingResponse> {
+        for name in self.headers.keys() {
+            if !request.headers().contains_key(name) {
+                for value in self.headers.get_all(name) {
+                    request.headers_mut().append(name, value.clone());
+                }
+            }
+        }
+        next.run(request).await
+    }
+}
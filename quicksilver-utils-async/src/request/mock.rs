@@ -0,0 +1,189 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::future::ready;
+use futures_util::stream::{self, StreamExt};
+use http::header::CONTENT_TYPE;
+use http::{Method, StatusCode, Uri};
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+#[cfg(feature = "protobuf")]
+use crate::codec::Protobuf;
+use crate::codec::{Decode, Encode};
+use crate::time::sleep;
+
+use super::{
+    clone_request, Client, HttpTransport, Request, RequestError, Response, Result,
+    StreamingResponse,
+};
+
+type Handler = dyn Fn(Request) -> Result<Response>;
+
+struct Route {
+    method: Method,
+    uri: Uri,
+    handler: Box<Handler>,
+}
+
+/// A client whose requests are answered by a `MockTransport`
+pub type MockServiceClient = Client<MockTransport>;
+
+/// Answers requests from handlers registered per method and `Uri`, and
+/// records every request it sees, for testing code built on a
+/// `ServiceClient` without a server
+///
+/// Requests without a matching handler get a 404. When several handlers
+/// match, the one registered last wins, so a test can override a default.
+/// A handler returning an error simulates a failed request, and
+/// `set_latency` delays every response.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::request::*;
+/// # use http::Method;
+/// let client = MockServiceClient::new();
+/// client.transport().respond(Method::GET, "http://localhost/scores/monk", 200, "9000");
+/// client.transport().on(Method::PUT, "http://localhost/scores/monk", |_request| {
+///     Err(RequestError::Transport("connection reset".to_string()))
+/// });
+/// ```
+#[derive(Default)]
+pub struct MockTransport {
+    routes: RefCell<Vec<Route>>,
+    requests: RefCell<Vec<Request>>,
+    latency: Cell<Duration>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport::default()
+    }
+
+    /// Answer <method> requests to <uri> with <handler>
+    ///
+    /// Panics if <uri> is not a valid `Uri`.
+    pub fn on<F>(&self, method: Method, uri: &str, handler: F)
+    where
+        F: Fn(Request) -> Result<Response> + 'static,
+    {
+        let uri = uri.parse().expect("invalid mock uri");
+        self.routes.borrow_mut().push(Route {
+            method,
+            uri,
+            handler: Box::new(handler),
+        });
+    }
+
+    /// Answer <method> requests to <uri> with a canned <status> and <body>
+    pub fn respond(&self, method: Method, uri: &str, status: u16, body: impl Into<Bytes>) {
+        let body = body.into();
+        self.on(method, uri, move |_request| {
+            Ok(http::Response::builder()
+                .status(status)
+                .body(body.clone())
+                .expect("canned status is valid"))
+        });
+    }
+
+    /// Answer POST requests to <uri> by decoding the body with the codec
+    /// `C`, calling <handler>, and encoding its result with `C`
+    ///
+    /// A body that can't be decoded gets a 400. A handler returning
+    /// `RequestError::Http` or `RequestError::Unauthorized` answers with
+    /// that status; any other error fails the request.
+    pub fn on_call<RequestT, ResponseT, C, F>(&self, uri: &str, handler: F)
+    where
+        C: Decode<RequestT> + Encode<ResponseT>,
+        F: Fn(RequestT) -> Result<ResponseT> + 'static,
+    {
+        self.on(Method::POST, uri, move |request| {
+            let payload = match C::decode(request.body()) {
+                Ok(payload) => payload,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+            };
+            match handler(payload) {
+                Ok(response) => Ok(http::Response::builder()
+                    .header(CONTENT_TYPE, C::content_type())
+                    .body(C::encode(&response)?)
+                    .expect("codec content type is valid")),
+                Err(RequestError::Http { status, body }) => error_response(status, body),
+                Err(RequestError::Unauthorized) => error_response(StatusCode::UNAUTHORIZED, ""),
+                Err(e) => Err(e),
+            }
+        });
+    }
+
+    /// Answer `ServiceClient::post_proto` calls to <uri> with <handler>,
+    /// see `on_call`
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::request::*;
+    /// # use protobuf::well_known_types::StringValue;
+    /// let client = MockServiceClient::new();
+    /// client.transport().on_proto("http://localhost/shout", |request: StringValue| {
+    ///     let mut response = StringValue::new();
+    ///     response.set_value(request.get_value().to_uppercase());
+    ///     Ok(response)
+    /// });
+    /// ```
+    #[cfg(feature = "protobuf")]
+    pub fn on_proto<RequestT, ResponseT, F>(&self, uri: &str, handler: F)
+    where
+        RequestT: protobuf::Message,
+        ResponseT: protobuf::Message,
+        F: Fn(RequestT) -> Result<ResponseT> + 'static,
+    {
+        self.on_call::<RequestT, ResponseT, Protobuf, F>(uri, handler)
+    }
+
+    /// Wait <latency> before answering each request
+    pub fn set_latency(&self, latency: Duration) {
+        self.latency.set(latency)
+    }
+
+    /// Every request sent so far, oldest first
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.borrow().iter().map(clone_request).collect()
+    }
+
+    pub fn clear_requests(&self) {
+        self.requests.borrow_mut().clear()
+    }
+
+    fn answer(&self, request: Request) -> Result<Response> {
+        let routes = self.routes.borrow();
+        let route = routes
+            .iter()
+            .rev()
+            .find(|route| route.method == request.method() && route.uri == *request.uri());
+        match route {
+            Some(route) => (route.handler)(request),
+            None => error_response(
+                StatusCode::NOT_FOUND,
+                format!("No mock for {} {}", request.method(), request.uri()),
+            ),
+        }
+    }
+}
+
+fn error_response(status: StatusCode, body: impl Into<Bytes>) -> Result<Response> {
+    Ok(http::Response::builder()
+        .status(status)
+        .body(body.into())
+        .expect("status is valid"))
+}
+
+#[async_trait(?Send)]
+impl HttpTransport for MockTransport {
+    async fn send(&self, request: Request) -> Result<StreamingResponse> {
+        self.requests.borrow_mut().push(clone_request(&request));
+        let latency = self.latency.get();
+        if latency > Duration::from_secs(0) {
+            sleep(latency).await;
+        }
+        let (parts, body) = self.answer(request)?.into_parts();
+        let body = stream::once(ready(Ok(body))).boxed_local();
+        Ok(StreamingResponse::from_parts(parts, body))
+    }
+}
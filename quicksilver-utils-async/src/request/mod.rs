@@ -0,0 +1,555 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures_util::stream::{LocalBoxStream, StreamExt};
+use http::header::{HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use http::{Method, StatusCode, Uri};
+use log::{debug, trace};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+#[cfg(feature = "protobuf")]
+use crate::codec::Protobuf;
+use crate::codec::{Decode, Encode};
+
+/// Why a request failed
+#[derive(Debug)]
+pub enum RequestError {
+    /// The request or its payload could not be encoded
+    Encode(String),
+    /// The response body could not be decoded
+    Decode(String),
+    /// The server answered with a non-2xx status, other than 401
+    Http { status: StatusCode, body: Bytes },
+    /// No response arrived, or the connection broke while reading it
+    Transport(String),
+    /// The request took longer than it was allowed to
+    Timeout,
+    /// The server answered 401, the auth token is missing or stale
+    Unauthorized,
+    /// A gRPC call ended with a non-zero `grpc-status`
+    Grpc { code: u32, message: String },
+    /// A Twirp call failed with an error body
+    #[cfg(feature = "twirp")]
+    Twirp(twirp::TwirpError),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::Encode(message) => write!(f, "failed encoding request: {}", message),
+            RequestError::Decode(message) => write!(f, "failed decoding response: {}", message),
+            RequestError::Http { status, .. } => write!(f, "server returned {}", status),
+            RequestError::Transport(message) => write!(f, "request failed: {}", message),
+            RequestError::Timeout => write!(f, "request timed out"),
+            RequestError::Unauthorized => write!(f, "request was unauthorized"),
+            RequestError::Grpc { code, message } => {
+                write!(f, "call failed with grpc-status {}: {}", code, message)
+            }
+            #[cfg(feature = "twirp")]
+            RequestError::Twirp(error) => write!(f, "twirp call failed: {}", error),
+        }
+    }
+}
+
+impl Error for RequestError {}
+
+impl RequestError {
+    /// Whether the same request might succeed if tried again
+    ///
+    /// True for transport failures, timeouts, and the statuses servers use
+    /// for overload or temporary outages, including gRPC's and Twirp's
+    /// unavailable codes.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RequestError::Transport(_) | RequestError::Timeout => true,
+            RequestError::Http { status, .. } => is_retryable_status(*status),
+            // UNAVAILABLE
+            RequestError::Grpc { code: 14, .. } => true,
+            #[cfg(feature = "twirp")]
+            RequestError::Twirp(error) => error.code == twirp::ErrorCode::Unavailable,
+            _ => false,
+        }
+    }
+}
+
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+pub type Result<T> = std::result::Result<T, RequestError>;
+
+/// Turn a non-2xx <response> into the matching `RequestError`
+///
+/// ```no_run
+/// # use quicksilver_utils_async::request::*;
+/// # async fn example(client: ServiceClientImpl) -> Result<()> {
+/// let response = client.get("https://example.com/scores".parse().unwrap()).send().await?;
+/// let scores = error_for_status(response)?.into_body();
+/// # Ok(())
+/// # }
+/// ```
+pub fn error_for_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else if status == StatusCode::UNAUTHORIZED {
+        Err(RequestError::Unauthorized)
+    } else {
+        Err(RequestError::Http {
+            status,
+            body: response.into_body(),
+        })
+    }
+}
+
+/// A copy of <request>, for sending it again
+pub(crate) fn clone_request(request: &Request) -> Request {
+    let mut clone = http::Request::new(request.body().clone());
+    *clone.method_mut() = request.method().clone();
+    *clone.uri_mut() = request.uri().clone();
+    *clone.version_mut() = request.version();
+    *clone.headers_mut() = request.headers().clone();
+    clone
+}
+
+/// A request with its whole body in memory
+pub type Request = http::Request<Bytes>;
+
+/// A response with its whole body in memory
+pub type Response = http::Response<Bytes>;
+
+/// A response body, delivered in chunks as it arrives
+pub type BodyStream = LocalBoxStream<'static, Result<Bytes>>;
+
+/// A response whose body is still arriving
+pub type StreamingResponse = http::Response<BodyStream>;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::desktop::{cache::DirectoryStorage, request::SurfTransport};
+
+#[cfg(all(target_arch = "wasm32", feature = "stdweb"))]
+pub use crate::std_web::{cache::LocalStorage, request::XhrTransport};
+
+#[cfg(all(target_arch = "wasm32", feature = "web-sys"))]
+pub use crate::web_sys::{
+    cache::LocalStorage,
+    request::{FetchCredentials, FetchMode, FetchTransport},
+};
+
+/// The transport `ServiceClientImpl` uses on this platform
+#[cfg(not(target_arch = "wasm32"))]
+pub type PlatformTransport = SurfTransport;
+
+/// The transport `ServiceClientImpl` uses on this platform
+#[cfg(all(target_arch = "wasm32", feature = "stdweb"))]
+pub type PlatformTransport = XhrTransport;
+
+/// The transport `ServiceClientImpl` uses on this platform
+#[cfg(all(target_arch = "wasm32", feature = "web-sys"))]
+pub type PlatformTransport = FetchTransport;
+
+/// The client for this platform
+pub type ServiceClientImpl = Client<PlatformTransport>;
+
+mod cache;
+mod download;
+mod form;
+#[cfg(feature = "protobuf")]
+pub mod grpc_web;
+mod loopback;
+pub mod middleware;
+mod mock;
+mod retry;
+#[cfg(feature = "twirp")]
+pub mod twirp;
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) use cache::{from_hex, to_hex};
+pub use cache::{CacheStorage, CachingClient, DEFAULT_CAPACITY};
+pub use download::{Download, DownloadEvent, Progress};
+pub use form::Multipart;
+pub use loopback::LoopbackTransport;
+pub use mock::{MockServiceClient, MockTransport};
+pub use retry::{RetryClient, RetryPolicy};
+
+/// Moves requests and responses over the wire, one implementation per
+/// platform
+///
+/// Everything else, from the request builder to codecs and retries,
+/// is shared on top of this through `Client`.
+#[async_trait(?Send)]
+pub trait HttpTransport {
+    /// Send <request>, handing back the response as soon as its status
+    /// and headers are known
+    async fn send(&self, request: Request) -> Result<StreamingResponse>;
+}
+
+/// Build the response a transport received, headers that `http` can't
+/// represent are a transport error
+pub(crate) fn build_response<N, V>(
+    status: u16,
+    headers: impl IntoIterator<Item = (N, V)>,
+    body: BodyStream,
+) -> Result<StreamingResponse>
+where
+    N: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut builder = http::Response::builder().status(status);
+    for (name, value) in headers {
+        builder = builder.header(name.as_ref(), value.as_ref());
+    }
+    builder
+        .body(body)
+        .map_err(|e| RequestError::Transport(format!("Invalid response {}", e)))
+}
+
+/// A `ServiceClient` sending requests over an `HttpTransport`
+#[derive(Clone, Debug, Default)]
+pub struct Client<T> {
+    transport: T,
+}
+
+impl<T: HttpTransport> Client<T> {
+    pub fn with_transport(transport: T) -> Self {
+        Client { transport }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: HttpTransport + Default> ServiceClient for Client<T> {
+    fn new() -> Self {
+        Client::with_transport(T::default())
+    }
+
+    async fn send_streaming(&self, request: Request) -> Result<StreamingResponse> {
+        debug!("{} {}", request.method(), request.uri());
+        let response = self.transport.send(request).await;
+        if let Ok(response) = &response {
+            trace!("Response status {}", response.status());
+        }
+        response
+    }
+}
+
+/// Fetch <url> as text with a platform client, failing on a non-2xx
+/// status
+pub async fn get_resource(url: &str) -> Result<String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| RequestError::Encode(format!("Invalid url {}", e)))?;
+    let response = ServiceClientImpl::new().get(uri).send().await?;
+    let body = error_for_status(response)?.into_body();
+    String::from_utf8(body.to_vec())
+        .map_err(|e| RequestError::Decode(format!("Invalid utf8 {}", e)))
+}
+
+// Futures here are not Send, the browser backends hold JS objects and
+// the rest of the crate is single-threaded anyway
+#[async_trait(?Send)]
+pub trait ServiceClient {
+    fn new() -> Self;
+
+    /// Send <request>, handing back the response as soon as its status
+    /// and headers are known
+    ///
+    /// A non-2xx status is not an error, check `Response::status`.
+    async fn send_streaming(&self, request: Request) -> Result<StreamingResponse>;
+
+    /// Send <request> and wait for the whole response body
+    async fn send(&self, request: Request) -> Result<Response> {
+        let (parts, mut body) = self.send_streaming(request).await?.into_parts();
+        let mut collected = BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            collected.extend_from_slice(&chunk?);
+        }
+        Ok(Response::from_parts(parts, collected.freeze()))
+    }
+
+    /// Start building a request to send with this client
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::request::{ServiceClient, ServiceClientImpl};
+    /// # async fn example() -> quicksilver_utils_async::request::Result<()> {
+    /// let client = ServiceClientImpl::new();
+    /// let response = client
+    ///     .get("https://jsonplaceholder.typicode.com/todos".parse().unwrap())
+    ///     .query(&[("userId", "1")])
+    ///     .header("Accept", "application/json")
+    ///     .send()
+    ///     .await?;
+    /// if response.status().is_success() {
+    ///     println!("{}", String::from_utf8_lossy(response.body()));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn request(&self, method: Method, uri: Uri) -> RequestBuilder<'_, Self>
+    where
+        Self: Sized,
+    {
+        RequestBuilder::new(self, method, uri)
+    }
+
+    fn get(&self, uri: Uri) -> RequestBuilder<'_, Self>
+    where
+        Self: Sized,
+    {
+        self.request(Method::GET, uri)
+    }
+
+    fn put(&self, uri: Uri) -> RequestBuilder<'_, Self>
+    where
+        Self: Sized,
+    {
+        self.request(Method::PUT, uri)
+    }
+
+    fn delete(&self, uri: Uri) -> RequestBuilder<'_, Self>
+    where
+        Self: Sized,
+    {
+        self.request(Method::DELETE, uri)
+    }
+
+    async fn post_raw(&self, uri: Uri, request_body: Bytes) -> Result<Bytes>
+    where
+        Self: Sized,
+    {
+        let response = self
+            .request(Method::POST, uri)
+            .header(ACCEPT, "application/octet-stream")
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(request_body)
+            .send()
+            .await?;
+
+        Ok(error_for_status(response)?.into_body())
+    }
+
+    /// Post <request_payload> encoded with the codec `C`, and decode the
+    /// response with it, see the `codec` module
+    async fn post<RequestT, ResponseT, C>(
+        &self,
+        uri: Uri,
+        request_payload: &RequestT,
+    ) -> Result<ResponseT>
+    where
+        Self: Sized,
+        C: Encode<RequestT> + Decode<ResponseT>,
+    {
+        let request_body = C::encode(request_payload)?;
+        trace!("Request bytes: {:?}", request_body);
+        let response = self
+            .request(Method::POST, uri)
+            .header(ACCEPT, C::content_type())
+            .header(CONTENT_TYPE, C::content_type())
+            .body(request_body)
+            .send()
+            .await?;
+        let response = error_for_status(response)?;
+
+        trace!("Response bytes: {:?}", response.body());
+        C::decode(response.body())
+    }
+
+    #[cfg(feature = "protobuf")]
+    async fn post_proto<RequestT, ResponseT>(
+        &self,
+        uri: Uri,
+        request_payload: &RequestT,
+    ) -> Result<ResponseT>
+    where
+        Self: Sized,
+        RequestT: protobuf::Message,
+        ResponseT: protobuf::Message,
+    {
+        self.post::<RequestT, ResponseT, Protobuf>(uri, request_payload)
+            .await
+    }
+}
+
+/// A request under construction, see `ServiceClient::request`
+///
+/// Invalid headers or URIs are remembered and reported when the request
+/// is built or sent.
+pub struct RequestBuilder<'a, C> {
+    client: &'a C,
+    builder: http::request::Builder,
+    body: Bytes,
+    error: Option<http::Error>,
+}
+
+impl<'a, C: ServiceClient> RequestBuilder<'a, C> {
+    fn new(client: &'a C, method: Method, uri: Uri) -> Self {
+        RequestBuilder {
+            client,
+            builder: http::Request::builder().method(method).uri(uri),
+            body: Bytes::new(),
+            error: None,
+        }
+    }
+
+    /// Add a header, keeping any earlier values for the same name
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.builder = self.builder.header(key, value);
+        self
+    }
+
+    /// Append url-encoded <pairs> to the query string
+    pub fn query<K, V>(mut self, pairs: &[(K, V)]) -> Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let uri = match self.builder.uri_ref() {
+            Some(uri) => uri.clone(),
+            None => return self,
+        };
+        let query = uri.query().unwrap_or("").to_string();
+        let mut serializer = url::form_urlencoded::Serializer::for_suffix(query, 0);
+        for (key, value) in pairs {
+            serializer.append_pair(key.as_ref(), value.as_ref());
+        }
+        let query = serializer.finish();
+        // a bare `?` would still change the uri, and so cache keys
+        if query.is_empty() {
+            return self;
+        }
+        let path_and_query = format!("{}?{}", uri.path(), query);
+
+        let mut parts = uri.into_parts();
+        let uri = path_and_query
+            .parse()
+            .map_err(http::Error::from)
+            .and_then(|path_and_query| {
+                parts.path_and_query = Some(path_and_query);
+                Uri::from_parts(parts).map_err(http::Error::from)
+            });
+        match uri {
+            Ok(uri) => self.builder = self.builder.uri(uri),
+            Err(e) => self.error = self.error.or(Some(e)),
+        }
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Set a header, replacing any earlier values for the same name
+    fn replace_header(mut self, key: HeaderName, value: HeaderValue) -> Self {
+        if let Some(headers) = self.builder.headers_mut() {
+            headers.insert(key, value);
+        }
+        self
+    }
+
+    /// Send url-encoded <pairs> as an `application/x-www-form-urlencoded`
+    /// body, replacing any `Content-Type` set earlier
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::request::*;
+    /// # use http::Method;
+    /// # async fn example(client: ServiceClientImpl) -> Result<()> {
+    /// client
+    ///     .request(Method::POST, "https://example.com/login".parse().unwrap())
+    ///     .form(&[("user", "monk"), ("greeting", "hi & bye")])
+    ///     .send()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn form<K, V>(self, pairs: &[(K, V)]) -> Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        for (key, value) in pairs {
+            serializer.append_pair(key.as_ref(), value.as_ref());
+        }
+        self.replace_header(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        )
+        .body(serializer.finish())
+    }
+
+    /// Send <form> as a `multipart/form-data` body, replacing any
+    /// `Content-Type` set earlier
+    pub fn multipart(mut self, mut form: Multipart) -> Self {
+        if let Some(e) = form.error.take() {
+            self.error = self.error.or(Some(e));
+        }
+        let content_type =
+            HeaderValue::try_from(form.content_type()).expect("the boundary is a valid header");
+        self.replace_header(CONTENT_TYPE, content_type)
+            .body(form.finish())
+    }
+
+    pub fn build(self) -> Result<Request> {
+        if let Some(e) = self.error {
+            return Err(RequestError::Encode(format!("Invalid request: {}", e)));
+        }
+        self.builder
+            .body(self.body)
+            .map_err(|e| RequestError::Encode(format!("Invalid request: {}", e)))
+    }
+
+    pub async fn send(self) -> Result<Response> {
+        let client = self.client;
+        client.send(self.build()?).await
+    }
+
+    pub async fn send_streaming(self) -> Result<StreamingResponse> {
+        let client = self.client;
+        client.send_streaming(self.build()?).await
+    }
+
+    /// Send the request, reporting progress as the response body
+    /// arrives
+    ///
+    /// The first event is sent once the response headers arrive.
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::request::*;
+    /// # use futures_util::stream::StreamExt;
+    /// # async fn example(client: ServiceClientImpl) -> Result<()> {
+    /// let mut download = client
+    ///     .get("https://example.com/level-1.map".parse().unwrap())
+    ///     .download()
+    ///     .await?;
+    /// while let Some(event) = download.next().await {
+    ///     match event? {
+    ///         DownloadEvent::Progress(progress) => println!("{:?}", progress.fraction()),
+    ///         DownloadEvent::Finished(response) => println!("{} bytes", response.body().len()),
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download(self) -> Result<Download> {
+        Ok(download::download(self.send_streaming().await?))
+    }
+}
@@ -0,0 +1,192 @@
+use async_trait::async_trait;
+use http::header::RETRY_AFTER;
+use http::{HeaderMap, Method};
+use log::debug;
+use std::future::Future;
+use std::time::Duration;
+
+use super::{
+    clone_request, is_retryable_status, Request, RequestError, Response, Result, ServiceClient,
+    StreamingResponse,
+};
+use crate::backoff::{Backoff, Rng};
+use crate::time::{sleep, timeout};
+
+/// When and how often `RetryClient` tries a request again
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, so at most `max_retries + 1` attempts
+    pub max_retries: u32,
+    /// The delay before the first retry
+    pub initial_backoff: Duration,
+    /// The longest delay between attempts, including `Retry-After` hints
+    pub max_backoff: Duration,
+    /// How much longer each delay is than the one before
+    pub multiplier: f64,
+    /// The fraction of each delay that is random, in `[0, 1]`, so that
+    /// clients that failed together don't retry together
+    pub jitter: f64,
+    /// How long each attempt may take, `None` waits forever
+    pub timeout: Option<Duration>,
+    /// Also retry methods that are not idempotent, like POST; only safe
+    /// when the server ignores duplicates
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.,
+            jitter: 0.5,
+            timeout: Some(Duration::from_secs(30)),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number <retry>, counting from zero, with
+    /// <random> in `[0, 1)` picking the jitter
+    fn backoff(&self, retry: u32, random: f64) -> Duration {
+        let backoff = Backoff {
+            initial: self.initial_backoff,
+            max: self.max_backoff,
+            multiplier: self.multiplier,
+            jitter: self.jitter,
+        };
+        backoff.delay(retry, random)
+    }
+}
+
+/// Wraps another client, retrying failed requests with exponential
+/// backoff and timing out slow ones
+///
+/// Transport failures, timeouts and 408, 429, 500, 502, 503 and 504
+/// responses are retried, but only for idempotent methods unless the
+/// policy says otherwise. A streaming response is not retried once it
+/// has been handed back.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::request::*;
+/// # use std::time::Duration;
+/// let policy = RetryPolicy {
+///     initial_backoff: Duration::from_millis(100),
+///     ..RetryPolicy::default()
+/// };
+/// let client = RetryClient::with_policy(ServiceClientImpl::new(), policy);
+/// ```
+pub struct RetryClient<C> {
+    inner: C,
+    policy: RetryPolicy,
+    rng: Rng,
+}
+
+impl<C: ServiceClient> RetryClient<C> {
+    pub fn with_policy(inner: C, policy: RetryPolicy) -> Self {
+        RetryClient {
+            inner,
+            policy,
+            rng: Rng::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy
+    }
+
+    async fn with_retries<B, F, Fut>(&self, request: Request, send: F) -> Result<http::Response<B>>
+    where
+        F: Fn(Request) -> Fut,
+        Fut: Future<Output = Result<http::Response<B>>>,
+    {
+        let may_retry = self.policy.retry_non_idempotent || is_idempotent(request.method());
+        let mut retry = 0;
+        loop {
+            let attempt = send(clone_request(&request));
+            let result = match self.policy.timeout {
+                Some(limit) => timeout(limit, attempt)
+                    .await
+                    .unwrap_or(Err(RequestError::Timeout)),
+                None => attempt.await,
+            };
+
+            let (should_retry, retry_after) = match &result {
+                Ok(response) => (
+                    is_retryable_status(response.status()),
+                    retry_after(response.headers()),
+                ),
+                Err(e) => (e.is_retryable(), None),
+            };
+            if !may_retry || !should_retry || retry >= self.policy.max_retries {
+                return result;
+            }
+
+            let mut delay = self.policy.backoff(retry, self.rng.next_f64());
+            if let Some(retry_after) = retry_after {
+                delay = delay.max(retry_after.min(self.policy.max_backoff));
+            }
+            debug!(
+                "Retrying {} {} in {:?}",
+                request.method(),
+                request.uri(),
+                delay
+            );
+            sleep(delay).await;
+            retry += 1;
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<C: ServiceClient> ServiceClient for RetryClient<C> {
+    fn new() -> Self {
+        RetryClient::with_policy(C::new(), RetryPolicy::default())
+    }
+
+    async fn send_streaming(&self, request: Request) -> Result<StreamingResponse> {
+        self.with_retries(request, |request| self.inner.send_streaming(request))
+            .await
+    }
+
+    // the timeout covers the whole body, not just the headers
+    async fn send(&self, request: Request) -> Result<Response> {
+        self.with_retries(request, |request| self.inner.send(request))
+            .await
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// The `Retry-After` header, only in its delay-seconds form
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds: u64 = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
@@ -0,0 +1,217 @@
+//! # twirp
+//!
+//! Typed clients for Twirp services, declared with the `twirp_service!`
+//! macro. A build script can generate the declarations from the
+//! service's `.proto` file with the `quicksilver-utils-twirp-build`
+//! crate. Each RPC becomes an async method posting the protobuf request
+//! to `<base url>/twirp/<package>.<Service>/<Method>`.
+//!
+//! Error responses with a Twirp JSON body become `RequestError::Twirp`,
+//! a 401 included, so its code and message are kept. Auth middleware
+//! still sees the 401 status of the response.
+//!
+//! # Examples
+//!
+//! For the service
+//!
+//! ```proto
+//! package demo.scores;
+//!
+//! service Scores {
+//!   rpc Submit(google.protobuf.UInt32Value) returns (google.protobuf.StringValue);
+//!   rpc Reset(google.protobuf.UInt32Value) returns (google.protobuf.StringValue);
+//! }
+//! ```
+//!
+//! ```no_run
+//! # use quicksilver_utils_async::request::*;
+//! # use quicksilver_utils_async::twirp_service;
+//! # use protobuf::well_known_types::{StringValue, UInt32Value};
+//! twirp_service! {
+//!     /// Submits high scores
+//!     pub struct ScoresClient for "demo.scores.Scores" {
+//!         /// Submit a score, getting back its rank
+//!         rpc Submit(UInt32Value) returns (StringValue) as submit;
+//!         /// Clear the board
+//!         rpc Reset(UInt32Value) returns (StringValue) as reset;
+//!     }
+//! }
+//!
+//! # async fn example(score: UInt32Value) -> Result<()> {
+//! let client = ScoresClient::new(ServiceClientImpl::new(), "https://example.com");
+//! let rank = client.submit(&score).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use bytes::Bytes;
+use http::header::{ACCEPT, CONTENT_TYPE};
+use http::{Method, Uri};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use super::{error_for_status, RequestError, Result, ServiceClient};
+use crate::codec::{Codec, Decode, Encode, Protobuf};
+
+/// Declare a typed client for a Twirp service, see the `request::twirp`
+/// module
+///
+/// The client wraps a `ServiceClient` and the base url of the server,
+/// and has an async method per RPC.
+#[macro_export]
+macro_rules! twirp_service {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $client:ident for $service:literal {
+            $(
+                $(#[$rpc_attr:meta])*
+                rpc $rpc:ident($request:ty) returns ($response:ty) as $method:ident;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $client<C> {
+            client: C,
+            base_url: String,
+        }
+
+        impl<C: $crate::request::ServiceClient> $client<C> {
+            /// Call the service on the server at <base_url> through <client>
+            pub fn new(client: C, base_url: &str) -> Self {
+                $client {
+                    client,
+                    base_url: base_url.to_string(),
+                }
+            }
+
+            pub fn inner(&self) -> &C {
+                &self.client
+            }
+
+            pub fn inner_mut(&mut self) -> &mut C {
+                &mut self.client
+            }
+
+            $(
+                $(#[$rpc_attr])*
+                pub async fn $method(&self, request: &$request) -> $crate::request::Result<$response> {
+                    $crate::request::twirp::call(
+                        &self.client,
+                        &self.base_url,
+                        $service,
+                        stringify!($rpc),
+                        request,
+                    )
+                    .await
+                }
+            )*
+        }
+    };
+}
+
+/// Binary protobuf bodies as Twirp expects them, sent as
+/// `application/protobuf`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TwirpProtobuf;
+
+impl Codec for TwirpProtobuf {
+    fn content_type() -> &'static str {
+        "application/protobuf"
+    }
+}
+
+impl<T: protobuf::Message> Encode<T> for TwirpProtobuf {
+    fn encode(value: &T) -> Result<Bytes> {
+        Protobuf::encode(value)
+    }
+}
+
+impl<T: protobuf::Message> Decode<T> for TwirpProtobuf {
+    fn decode(body: &[u8]) -> Result<T> {
+        Protobuf::decode(body)
+    }
+}
+
+/// The error codes of the Twirp spec
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Canceled,
+    InvalidArgument,
+    Malformed,
+    DeadlineExceeded,
+    NotFound,
+    BadRoute,
+    AlreadyExists,
+    PermissionDenied,
+    Unauthenticated,
+    ResourceExhausted,
+    FailedPrecondition,
+    Aborted,
+    OutOfRange,
+    Unimplemented,
+    Internal,
+    Unavailable,
+    DataLoss,
+    /// Also used for codes this client doesn't know
+    #[serde(other)]
+    Unknown,
+}
+
+/// The JSON body of a Twirp error response
+#[derive(Clone, Debug, Deserialize)]
+pub struct TwirpError {
+    pub code: ErrorCode,
+    pub msg: String,
+    #[serde(default)]
+    pub meta: HashMap<String, String>,
+}
+
+impl fmt::Display for TwirpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.msg)
+    }
+}
+
+impl Error for TwirpError {}
+
+/// Call <method> of <service> on the server at <base_url>, the body of
+/// the methods generated by `twirp_service!`
+pub async fn call<C, RequestT, ResponseT>(
+    client: &C,
+    base_url: &str,
+    service: &str,
+    method: &str,
+    request: &RequestT,
+) -> Result<ResponseT>
+where
+    C: ServiceClient,
+    RequestT: protobuf::Message,
+    ResponseT: protobuf::Message,
+{
+    let url = format!(
+        "{}/twirp/{}/{}",
+        base_url.trim_end_matches('/'),
+        service,
+        method
+    );
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| RequestError::Encode(format!("Invalid url {}", e)))?;
+    let response = client
+        .request(Method::POST, uri)
+        .header(ACCEPT, TwirpProtobuf::content_type())
+        .header(CONTENT_TYPE, TwirpProtobuf::content_type())
+        .body(TwirpProtobuf::encode(request)?)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        // not from Twirp if it doesn't parse, maybe a proxy in the way
+        if let Ok(error) = serde_json::from_slice(response.body()) {
+            return Err(RequestError::Twirp(error));
+        }
+    }
+    TwirpProtobuf::decode(error_for_status(response)?.body())
+}
@@ -0,0 +1,44 @@
+use crate::request::{from_hex, to_hex, CacheStorage};
+use log::warn;
+use std_web::web::window;
+
+/// Keeps cached responses in the browser's localStorage, under keys
+/// starting with a prefix
+///
+/// localStorage only holds strings, so entries are stored as hex.
+#[derive(Clone, Debug)]
+pub struct LocalStorage {
+    prefix: String,
+}
+
+impl LocalStorage {
+    pub fn new(prefix: &str) -> Self {
+        LocalStorage {
+            prefix: prefix.to_string(),
+        }
+    }
+}
+
+impl CacheStorage for LocalStorage {
+    fn load(&self, key: &str) -> Option<Vec<u8>> {
+        let hex = window()
+            .local_storage()
+            .get(&format!("{}{}", self.prefix, key))?;
+        from_hex(&hex)
+    }
+
+    fn store(&self, key: &str, entry: &[u8]) {
+        let stored = window()
+            .local_storage()
+            .insert(&format!("{}{}", self.prefix, key), &to_hex(entry));
+        if stored.is_err() {
+            warn!("Failed caching {}, localStorage is full", key);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        window()
+            .local_storage()
+            .remove(&format!("{}{}", self.prefix, key));
+    }
+}
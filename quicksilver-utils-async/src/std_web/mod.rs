@@ -1,5 +1,6 @@
 extern crate std_web;
 
+pub(crate) mod cache;
 pub(crate) mod request;
 pub(crate) mod time;
 pub(crate) mod websocket;
@@ -1,87 +1,192 @@
-use crate::request::RequestError;
+use crate::request::{
+    build_response, HttpTransport, Request, RequestError, Result, StreamingResponse,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
 use futures_util::future::poll_fn;
+use futures_util::stream::{self, StreamExt};
 use log::debug;
 use std::cell::RefCell;
-use std::sync::Arc;
-use std::task::Poll;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
 use std_web::{
+    js,
     traits::*,
     unstable::TryInto,
     web::{
-        event::{ProgressAbortEvent, ProgressLoadEvent},
-        ArrayBuffer, TypedArray, XhrReadyState, XhrResponseType, XmlHttpRequest,
+        event::{ProgressEvent, ReadyStateChangeEvent},
+        EventListenerHandle, TypedArray, XhrReadyState, XmlHttpRequest,
     },
-    Reference,
+    Reference, Value,
 };
 
-struct XhrClosureInner {
+/// Wakes the task waiting on an XHR each time its state changes or
+/// more of the body arrives, until dropped
+struct XhrEvents {
+    waker: Rc<RefCell<Option<Waker>>>,
+    handles: Vec<EventListenerHandle>,
+}
+
+impl XhrEvents {
+    fn listen(xhr: &XmlHttpRequest) -> Self {
+        let waker: Rc<RefCell<Option<Waker>>> = Rc::new(RefCell::new(None));
+        let wake = |waker: &Rc<RefCell<Option<Waker>>>| {
+            if let Some(waker) = waker.borrow().as_ref() {
+                waker.wake_by_ref()
+            }
+        };
+        let handles = vec![
+            xhr.add_event_listener({
+                let waker = waker.clone();
+                move |_: ReadyStateChangeEvent| wake(&waker)
+            }),
+            xhr.add_event_listener({
+                let waker = waker.clone();
+                move |_: ProgressEvent| wake(&waker)
+            }),
+        ];
+        XhrEvents { waker, handles }
+    }
+
+    fn register(&self, cx: &Context) {
+        self.waker.borrow_mut().replace(cx.waker().clone());
+    }
+}
+
+impl Drop for XhrEvents {
+    fn drop(&mut self) {
+        for handle in self.handles.drain(..) {
+            handle.remove()
+        }
+    }
+}
+
+/// A response body arriving over an XHR
+struct XhrBody {
     xhr: XmlHttpRequest,
-    have_set_handlers: bool,
+    events: XhrEvents,
+    /// How much of the response text has been handed over
+    read: u32,
 }
 
-struct XhrClosure {
-    inner: Arc<RefCell<XhrClosureInner>>,
+impl XhrBody {
+    /// The bytes that arrived since the last call, empty if none did
+    fn take_arrived(&mut self) -> Vec<u8> {
+        // with the x-user-defined charset each byte is one char, the
+        // upper ones mapped to U+F780 and up
+        let bytes: Reference = js!(
+            var text = @{&self.xhr}.responseText;
+            var bytes = new Uint8Array(text.length - @{self.read});
+            for (var i = 0; i < bytes.length; i++) {
+                bytes[i] = text.charCodeAt(@{self.read} + i) & 0xff;
+            }
+            return bytes;
+        )
+        .try_into()
+        .expect("The bytes are always a JS object");
+        let bytes: Vec<u8> = bytes
+            .downcast::<TypedArray<u8>>()
+            .expect("The bytes are always a Uint8Array")
+            .into();
+        self.read += bytes.len() as u32;
+        bytes
+    }
+
+    fn poll_chunk(&mut self, cx: &Context) -> Poll<Option<Result<Bytes>>> {
+        self.events.register(cx);
+        let arrived = self.take_arrived();
+        if !arrived.is_empty() {
+            return Poll::Ready(Some(Ok(Bytes::from(arrived))));
+        }
+        match self.xhr.ready_state() {
+            // network errors and aborts look like status 0
+            XhrReadyState::Done if self.xhr.status() == 0 => Poll::Ready(Some(Err(
+                RequestError::Transport("Failed reading body".to_string()),
+            ))),
+            XhrReadyState::Done => Poll::Ready(None),
+            _ => Poll::Pending,
+        }
+    }
 }
 
-pub async fn get_resource(url: &str) -> Result<String, RequestError> {
-    debug!("stdweb get request {}", url);
-    let xhr = XmlHttpRequest::new();
-    xhr.open("GET", url)
-        .map_err(|e| RequestError::NativeError(format!("Open: {}", e)))?;
-    xhr.set_response_type(XhrResponseType::ArrayBuffer)
-        .map_err(|e| RequestError::NativeError(format!("Set Response Type: {}", e)))?;
-    xhr.send()
-        .map_err(|e| RequestError::NativeError(format!("Send: {}", e)))?;
+impl Drop for XhrBody {
+    fn drop(&mut self) {
+        // nobody is reading the rest
+        if self.xhr.ready_state() != XhrReadyState::Done {
+            self.xhr.abort();
+        }
+    }
+}
 
-    let xhr_closure = XhrClosure {
-        inner: Arc::new(RefCell::new(XhrClosureInner {
-            xhr,
-            have_set_handlers: false,
-        })),
-    };
+/// Sends requests with XMLHttpRequest
+///
+/// The response is handed back once its headers arrive, and the body
+/// streams in chunks as XHR reports progress. The body is received as
+/// text in the `x-user-defined` charset, which maps each byte to one
+/// char, as XHR only hands over a binary body once all of it arrived.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XhrTransport;
 
-    let result = poll_fn(move |ctx| {
-        debug!("stdweb get request Polling");
-        let inner: &mut XhrClosureInner = &mut *xhr_closure.inner.borrow_mut();
+#[async_trait(?Send)]
+impl HttpTransport for XhrTransport {
+    async fn send(&self, request: Request) -> Result<StreamingResponse> {
+        let (parts, body) = request.into_parts();
+        let url = format!("{}", parts.uri);
 
-        if !inner.have_set_handlers {
-            inner.have_set_handlers = true;
-            let waker = ctx.waker().clone();
-            inner
-                .xhr
-                .add_event_listener(move |_: ProgressLoadEvent| waker.wake_by_ref());
-            let waker = ctx.waker().clone();
-            inner
-                .xhr
-                .add_event_listener(move |_: ProgressAbortEvent| waker.wake_by_ref());
+        let xhr = XmlHttpRequest::new();
+        xhr.open(parts.method.as_str(), &url)
+            .map_err(|e| RequestError::Transport(format!("Open: {}", e)))?;
+        js!( @{&xhr}.overrideMimeType("text/plain; charset=x-user-defined"); );
+        for (name, value) in parts.headers.iter() {
+            let value = value
+                .to_str()
+                .map_err(|e| RequestError::Encode(format!("Invalid header {}", e)))?;
+            xhr.set_request_header(name.as_str(), value)
+                .map_err(|e| RequestError::Transport(format!("Set Header: {}", e)))?;
         }
+        let response = XhrBody {
+            events: XhrEvents::listen(&xhr),
+            xhr,
+            read: 0,
+        };
+        if body.is_empty() {
+            response.xhr.send()
+        } else {
+            response.xhr.send_with_bytes(&body)
+        }
+        .map_err(|e| RequestError::Transport(format!("Send: {}", e)))?;
 
-        let status = inner.xhr.status();
-        let ready_state = inner.xhr.ready_state();
-        match (status / 100, ready_state) {
-            (2, XhrReadyState::Done) => {
-                let reference: Reference = inner
-                    .xhr
-                    .raw_response()
-                    .try_into()
-                    .expect("The response will always be a JS object");
-                Poll::Ready(
-                    reference
-                        .downcast::<ArrayBuffer>()
-                        .map(|arr| TypedArray::<u8>::from(arr).to_vec())
-                        .ok_or_else(|| {
-                            RequestError::NativeError("Failed to cast file into bytes".to_string())
-                        }),
-                )
+        poll_fn(|cx| {
+            debug!("stdweb request Polling");
+            response.events.register(cx);
+            match response.xhr.ready_state() {
+                XhrReadyState::Unsent | XhrReadyState::Opened => Poll::Pending,
+                _ => Poll::Ready(()),
             }
-            (2, _) => Poll::Pending,
-            (0, _) => Poll::Pending,
-            _ => Poll::Ready(Err(RequestError::NativeError(
-                "Non-200 status code returned".to_string(),
-            ))),
+        })
+        .await;
+        // network errors, CORS failures and aborts all look like status 0
+        if response.xhr.status() == 0 {
+            return Err(RequestError::Transport(
+                "Request did not complete".to_string(),
+            ));
         }
-    })
-    .await?;
 
-    String::from_utf8(result).map_err(|e| RequestError::NativeError(format!("Invalid utf8 {}", e)))
+        let raw_headers: Value = js!( return @{&response.xhr}.getAllResponseHeaders(); );
+        let raw_headers = raw_headers.into_string().unwrap_or_default();
+        let headers = raw_headers.split("\r\n").filter_map(|line| {
+            let colon = line.find(':')?;
+            Some((line[..colon].trim(), line[colon + 1..].trim()))
+        });
+
+        let status = response.xhr.status();
+        let body = stream::unfold(Some(response), |response| async move {
+            let mut response = response?;
+            match poll_fn(|cx| response.poll_chunk(cx)).await? {
+                Ok(chunk) => Some((Ok(chunk), Some(response))),
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+        build_response(status, headers, body.boxed_local())
+    }
 }
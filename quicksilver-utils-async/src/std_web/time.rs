@@ -1,4 +1,21 @@
-use std_web::web::wait;
+use std::time::Duration;
+use std_web::{js, unstable::TryInto, web::wait};
+
+/// Time since the page loaded, from the monotonic `performance.now()`
+pub fn now() -> Duration {
+    let ms: f64 = js!( return performance.now(); )
+        .try_into()
+        .expect("performance.now() returns a number");
+    Duration::from_secs_f64(ms / 1000.)
+}
+
+/// Time since the Unix epoch, from the wall clock
+pub fn unix_time() -> Duration {
+    let ms: f64 = js!( return Date.now(); )
+        .try_into()
+        .expect("Date.now() returns a number");
+    Duration::from_millis(ms as u64)
+}
 
 pub async fn sleep_ms(ms: u32) {
     wait(ms).await
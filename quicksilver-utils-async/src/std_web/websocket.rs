@@ -1,7 +1,8 @@
+use bytes::Bytes;
 use futures_util::future::poll_fn;
 use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::sync::Arc;
+use std::rc::Rc;
 use std::task::{Poll, Waker};
 use url::Url;
 
@@ -11,10 +12,11 @@ use std_web::web::{
         IMessageEvent, SocketCloseEvent, SocketErrorEvent, SocketMessageData, SocketMessageEvent,
         SocketOpenEvent,
     },
-    IEventTarget, SocketBinaryType, TypedArray, WebSocket,
+    IEventTarget, SocketBinaryType, SocketCloseCode, TypedArray, WebSocket,
 };
 
-use crate::websocket::{WebSocketError, WebSocketMessage};
+use crate::time::Instant;
+use crate::websocket::{within, WebSocketConfig, WebSocketError, WebSocketMessage};
 
 use log::{debug, trace};
 
@@ -30,12 +32,30 @@ enum SocketState {
 struct AsyncWebSocketInner {
     ws: WebSocket,
     state: SocketState,
-    waker: Option<Waker>,
+    /// Every task waiting on the socket, since clones and split halves
+    /// wait on it at the same time
+    wakers: Vec<Waker>,
     buffer: VecDeque<SocketMessageEvent>,
+    /// The close event, until received after the buffered messages
+    close: Option<WebSocketMessage>,
+}
+
+impl AsyncWebSocketInner {
+    fn wait(&mut self, waker: &Waker) {
+        if !self.wakers.iter().any(|waiting| waiting.will_wake(waker)) {
+            self.wakers.push(waker.clone());
+        }
+    }
+
+    fn wake_all(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake()
+        }
+    }
 }
 
 pub struct AsyncWebSocket {
-    inner: Arc<RefCell<AsyncWebSocketInner>>,
+    inner: Rc<RefCell<AsyncWebSocketInner>>,
 }
 
 impl Clone for AsyncWebSocket {
@@ -47,22 +67,24 @@ impl Clone for AsyncWebSocket {
 }
 
 impl AsyncWebSocket {
-    pub async fn connect(url: &Url) -> Result<Self, WebSocketError> {
-        let ws = WebSocket::new(url.as_str())
+    pub async fn connect(url: &Url, config: &WebSocketConfig) -> Result<Self, WebSocketError> {
+        let protocols: Vec<&str> = config.protocols.iter().map(String::as_str).collect();
+        let ws = WebSocket::new_with_protocols(url.as_str(), &protocols)
             .map_err(|_| WebSocketError::NativeError("Creation".to_string()))?;
         ws.set_binary_type(SocketBinaryType::ArrayBuffer);
 
         let async_ws: AsyncWebSocket = {
             let ws = ws.clone();
             let state = SocketState::Init;
-            let waker = None;
+            let wakers = Vec::new();
             let buffer = VecDeque::new();
 
-            let inner = Arc::new(RefCell::new(AsyncWebSocketInner {
+            let inner = Rc::new(RefCell::new(AsyncWebSocketInner {
                 ws,
                 state,
-                waker,
+                wakers,
                 buffer,
+                close: None,
             }));
             AsyncWebSocket { inner }
         };
@@ -73,21 +95,21 @@ impl AsyncWebSocket {
                 trace!("Websocket onopen callback!");
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 inner.state = SocketState::Open;
-                if let Some(waker) = inner.waker.take() {
-                    waker.wake()
-                }
+                inner.wake_all();
             }
         });
 
         ws.add_event_listener({
             let async_ws = async_ws.clone();
-            move |_: SocketCloseEvent| {
+            move |close_event: SocketCloseEvent| {
                 trace!("Websocket onclose callback!");
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 inner.state = SocketState::Closed;
-                if let Some(waker) = inner.waker.take() {
-                    waker.wake()
-                }
+                inner.close = Some(WebSocketMessage::Close {
+                    code: close_event.code().0,
+                    reason: close_event.reason(),
+                });
+                inner.wake_all();
             }
         });
 
@@ -98,9 +120,7 @@ impl AsyncWebSocket {
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 let error_message = format!("{:?}", error_event);
                 inner.state = SocketState::Error(error_message);
-                if let Some(waker) = inner.waker.take() {
-                    waker.wake()
-                }
+                inner.wake_all();
             }
         });
 
@@ -109,20 +129,18 @@ impl AsyncWebSocket {
             move |message_event: SocketMessageEvent| {
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 inner.buffer.push_back(message_event);
-                if let Some(waker) = inner.waker.take() {
-                    waker.wake()
-                }
+                inner.wake_all();
             }
         });
 
-        poll_fn({
+        let opened = poll_fn({
             let async_ws = async_ws.clone();
             move |cx| {
                 trace!("Polling");
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 match &inner.state {
                     SocketState::Init => {
-                        inner.waker.replace(cx.waker().clone());
+                        inner.wait(cx.waker());
                         Poll::Pending
                     }
                     SocketState::Open => Poll::Ready(Ok(())),
@@ -132,60 +150,94 @@ impl AsyncWebSocket {
                     SocketState::Closed => Poll::Ready(Err(WebSocketError::StateClosed)),
                 }
             }
-        })
-        .await?;
+        });
+        if let Err(e) = within(config.open_timeout(), opened).await {
+            ws.close();
+            return Err(e);
+        }
 
         Ok(async_ws)
     }
 
-    pub async fn send(&self, msg: &str) -> Result<(), WebSocketError> {
+    pub fn protocol(&self) -> Option<String> {
+        let protocol = self.inner.borrow().ws.protocol();
+        if protocol.is_empty() {
+            None
+        } else {
+            Some(protocol)
+        }
+    }
+
+    pub async fn send(&self, msg: &WebSocketMessage) -> Result<(), WebSocketError> {
         trace!("Send");
         let inner: &AsyncWebSocketInner = &self.inner.borrow();
-        inner
-            .ws
-            .send_text(msg)
-            .map_err(|_| WebSocketError::NativeError("Send".to_string()))?;
+        let sent = match msg {
+            WebSocketMessage::String(s) => inner.ws.send_text(s),
+            WebSocketMessage::Binary(b) => inner.ws.send_bytes(b),
+            WebSocketMessage::Close { code, reason } => {
+                return inner
+                    .ws
+                    .close_with_status(SocketCloseCode(*code), reason)
+                    .map_err(|_| WebSocketError::NativeError("Close".to_string()));
+            }
+        };
+        sent.map_err(|_| WebSocketError::NativeError("Send".to_string()))
+    }
+
+    /// Send <message>, browsers don't expose ping frames
+    pub async fn ping(&self, _id: u64, message: &WebSocketMessage) -> Result<(), WebSocketError> {
+        self.send(message).await
+    }
+
+    /// Always `None`, the answer to a ping is a message here
+    pub fn last_pong(&self) -> Option<(u64, Instant)> {
+        None
+    }
+
+    pub async fn close(&self) -> Result<(), WebSocketError> {
+        self.inner.borrow().ws.close();
         Ok(())
     }
 
     pub async fn receive(&self) -> Result<WebSocketMessage, WebSocketError> {
-        let message_event = poll_fn({
+        poll_fn({
             move |cx| {
                 trace!("Polling");
                 let inner: &mut AsyncWebSocketInner = &mut *self.inner.borrow_mut();
+                if let Some(message_event) = inner.buffer.pop_front() {
+                    return Poll::Ready(Ok(message(message_event)));
+                }
                 match &inner.state {
                     SocketState::Init => Poll::Ready(Err(WebSocketError::StateInit)),
                     SocketState::Open => {
-                        if let Some(ev) = inner.buffer.pop_front() {
-                            Poll::Ready(Ok(ev))
-                        } else {
-                            inner.waker.replace(cx.waker().clone());
-                            Poll::Pending
-                        }
+                        inner.wait(cx.waker());
+                        Poll::Pending
                     }
                     SocketState::Error(val) => {
                         Poll::Ready(Err(WebSocketError::StateError(val.clone())))
                     }
-                    SocketState::Closed => Poll::Ready(Err(WebSocketError::StateClosed)),
+                    SocketState::Closed => {
+                        Poll::Ready(inner.close.take().ok_or(WebSocketError::StateClosed))
+                    }
                 }
             }
         })
-        .await?;
+        .await
+    }
+}
 
-        let data = message_event.data();
-        debug!("{:?}", &data);
+fn message(message_event: SocketMessageEvent) -> WebSocketMessage {
+    let data = message_event.data();
+    debug!("{:?}", &data);
 
-        let message = match data {
-            SocketMessageData::Text(s) => WebSocketMessage::String(s),
-            SocketMessageData::ArrayBuffer(buf) => {
-                let t_buffer: TypedArray<u8> = TypedArray::from(buf);
-                WebSocketMessage::Binary(t_buffer.to_vec())
-            }
-            SocketMessageData::Blob(_) => {
-                panic!("binary should have been set to array buffer above...")
-            }
-        };
-
-        Ok(message)
+    match data {
+        SocketMessageData::Text(s) => WebSocketMessage::String(s),
+        SocketMessageData::ArrayBuffer(buf) => {
+            let t_buffer: TypedArray<u8> = TypedArray::from(buf);
+            WebSocketMessage::Binary(Bytes::from(t_buffer.to_vec()))
+        }
+        SocketMessageData::Blob(_) => {
+            panic!("binary should have been set to array buffer above...")
+        }
     }
 }
@@ -1,25 +1,108 @@
 use futures_util::{
-    future::{poll_fn, LocalFutureObj},
+    future::{abortable, poll_fn, AbortHandle, LocalFutureObj},
     stream::{FuturesUnordered, Stream},
-    task::LocalSpawnExt,
+    task::{waker_ref, ArcWake},
 };
 use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::task::{Poll, Waker};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
-pub struct TaskContext<'a, E> {
-    events: Arc<RefCell<Vec<E>>>,
-    futures: Arc<RefCell<FuturesUnordered<LocalFutureObj<'a, ()>>>>,
-    task_waker: Arc<RefCell<Option<Waker>>>,
+use crate::event_bus::{EventBus, Full, Subscriber};
+use crate::time::Instant;
+
+/// The order in which tasks get polled within one run of a `TaskContext`
+///
+/// Every ready `High` task is polled before any `Normal` task, and every
+/// `Normal` task before any `Low` task, so with a budget the low priority
+/// work is what gets deferred to a later frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+const PRIORITIES: usize = 3;
+
+type TaskPool<'a> = FuturesUnordered<LocalFutureObj<'a, ()>>;
+type SpawnQueue<'a> = Vec<(Priority, LocalFutureObj<'a, ()>)>;
+
+// How much polling the current run may still do
+#[derive(Default)]
+struct Budget {
+    polls_left: Option<usize>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    fn is_exhausted(&self) -> bool {
+        self.polls_left == Some(0)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    fn take_poll(&mut self) -> bool {
+        if self.is_exhausted() {
+            return false;
+        }
+        if let Some(polls_left) = self.polls_left.as_mut() {
+            *polls_left -= 1;
+        }
+        true
+    }
+}
+
+// Passes wakes on to the run's waker, noting that the pool has tasks
+// ready again
+struct PoolWaker {
+    woken: AtomicBool,
+    waker: Waker,
+}
+
+impl PoolWaker {
+    fn take_woken(&self) -> bool {
+        self.woken.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl ArcWake for PoolWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.store(true, Ordering::SeqCst);
+        arc_self.waker.wake_by_ref()
+    }
+}
+
+/// Runs cooperative tasks alongside the game loop
+///
+/// Tasks report back to the loop either through the single event type
+/// `E`, with `dispatch` and `drain`, or through the typed channels of
+/// the context's `EventBus`, with `publish` and `subscribe`.
+pub struct TaskContext<'a, E = ()> {
+    events: Rc<RefCell<Vec<E>>>,
+    bus: EventBus,
+    futures: Rc<RefCell<Vec<TaskPool<'a>>>>,
+    spawned: Rc<RefCell<SpawnQueue<'a>>>,
+    budget: Rc<RefCell<Budget>>,
+    task_waker: Rc<RefCell<Option<Waker>>>,
 }
 
 impl<'a, E> Clone for TaskContext<'a, E> {
     fn clone(&self) -> Self {
         TaskContext {
             events: self.events.clone(),
+            bus: self.bus.clone(),
             futures: self.futures.clone(),
+            spawned: self.spawned.clone(),
+            budget: self.budget.clone(),
             task_waker: self.task_waker.clone(),
         }
     }
@@ -28,9 +111,14 @@ impl<'a, E> Clone for TaskContext<'a, E> {
 impl<'a, E> Default for TaskContext<'a, E> {
     fn default() -> Self {
         TaskContext {
-            events: Arc::new(RefCell::new(Vec::new())),
-            futures: Arc::new(RefCell::new(FuturesUnordered::new())),
-            task_waker: Arc::new(RefCell::new(None)),
+            events: Rc::new(RefCell::new(Vec::new())),
+            bus: EventBus::new(),
+            futures: Rc::new(RefCell::new(
+                (0..PRIORITIES).map(|_| FuturesUnordered::new()).collect(),
+            )),
+            spawned: Rc::new(RefCell::new(Vec::new())),
+            budget: Rc::new(RefCell::new(Budget::default())),
+            task_waker: Rc::new(RefCell::new(None)),
         }
     }
 }
@@ -40,38 +128,195 @@ impl<'a, E> TaskContext<'a, E> {
         TaskContext::default()
     }
 
+    /// Poll tasks until every one of them is waiting
+    ///
+    /// That includes tasks woken by other tasks during the run, whatever
+    /// their priority. A task that keeps yielding keeps the run going, so
+    /// those are better run with `run_for` or `run_at_most`.
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::task_context::TaskContext;
+    /// # async fn example(mut task_context: TaskContext<'static, String>) {
+    /// task_context.run_until_stalled().await;
+    /// for event in task_context.drain() {
+    ///     println!("{}", event);
+    /// }
+    /// # }
+    /// ```
     pub async fn run_until_stalled(&mut self) {
-        poll_fn(move |cx| {
-            let mut x = self.futures.borrow_mut();
+        self.run_with_budget(Budget::default()).await
+    }
+
+    /// Poll tasks until they are all waiting, or <duration> has passed
+    ///
+    /// Tasks still ready when time runs out are polled on the next run.
+    /// A single poll is never interrupted, so long-running tasks should
+    /// `yield_now` regularly.
+    pub async fn run_for(&mut self, duration: Duration) {
+        self.run_with_budget(Budget {
+            polls_left: None,
+            deadline: Some(Instant::now() + duration),
+        })
+        .await
+    }
+
+    /// Poll tasks until they are all waiting, or <polls> task polls have
+    /// been made
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::task_context::TaskContext;
+    /// # async fn example(mut task_context: TaskContext<'static, String>) {
+    /// // leave the rest of the frame to rendering
+    /// task_context.run_at_most(100).await;
+    /// # }
+    /// ```
+    pub async fn run_at_most(&mut self, polls: usize) {
+        self.run_with_budget(Budget {
+            polls_left: Some(polls),
+            deadline: None,
+        })
+        .await
+    }
+
+    async fn run_with_budget(&mut self, budget: Budget) {
+        self.budget.replace(budget);
+        poll_fn(|cx| {
+            let mut pools = self.futures.borrow_mut();
+            let pool_wakers: Vec<Arc<PoolWaker>> = (0..PRIORITIES)
+                .map(|_| {
+                    Arc::new(PoolWaker {
+                        woken: AtomicBool::new(false),
+                        waker: cx.waker().clone(),
+                    })
+                })
+                .collect();
             loop {
-                let pinned_pool = Pin::new(&mut *x);
-                let pool_state = pinned_pool.poll_next(cx);
-                // trace!("Task context run pool_state: {:?}", pool_state);
-                match pool_state {
-                    Poll::Pending => break Poll::Ready(()),
-                    Poll::Ready(Some(_)) => {
-                        // debug!("Task finished");
-                        continue;
+                let mut priority = 0;
+                while priority < PRIORITIES {
+                    // tasks spawned by other tasks wait here until the pools are free
+                    for (task_priority, task) in self.spawned.borrow_mut().drain(..) {
+                        priority = priority.min(task_priority as usize);
+                        pools[task_priority as usize].push(task);
                     }
-                    Poll::Ready(None) => {
-                        self.task_waker.replace(Some(cx.waker().clone()));
-                        break Poll::Ready(());
+                    if self.budget.borrow().is_exhausted() {
+                        break;
                     }
+                    let pool_waker = &pool_wakers[priority];
+                    pool_waker.take_woken();
+                    let waker = waker_ref(pool_waker);
+                    let pinned_pool = Pin::new(&mut pools[priority]);
+                    let pool_state = pinned_pool.poll_next(&mut Context::from_waker(&waker));
+                    // trace!("Task context run pool_state: {:?}", pool_state);
+                    match pool_state {
+                        Poll::Ready(Some(_)) => {
+                            // debug!("Task finished");
+                            continue;
+                        }
+                        Poll::Pending | Poll::Ready(None) => {
+                            if self.spawned.borrow().is_empty() {
+                                priority += 1
+                            }
+                        }
+                    }
+                }
+                // A pool also reports pending when its tasks yield, and
+                // tasks in a pool already polled may have been woken by
+                // later ones, so go around again until nothing is ready
+                let woken = pool_wakers.iter().any(|pool_waker| pool_waker.take_woken());
+                if !woken || self.budget.borrow().is_exhausted() {
+                    break;
                 }
             }
+            if pools.iter().all(TaskPool::is_empty) {
+                self.task_waker.replace(Some(cx.waker().clone()));
+            }
+            Poll::Ready(())
         })
-        .await
+        .await;
+        self.budget.replace(Budget::default());
     }
 
-    pub fn spawn<Fut>(&mut self, task: Fut)
+    /// Start running <task> on this context
+    ///
+    /// The returned handle can be awaited for the task's output or used
+    /// to abort it; dropping the handle lets the task run on detached.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::task_context::TaskContext;
+    /// # async fn load_level() -> Vec<u8> { Vec::new() }
+    /// # async fn example(mut task_context: TaskContext<'static, ()>) {
+    /// let level = task_context.spawn(load_level());
+    /// task_context.run_until_stalled().await;
+    /// if level.is_finished() {
+    ///     let level = level.await;
+    /// }
+    /// # }
+    /// ```
+    pub fn spawn<Fut, T>(&mut self, task: Fut) -> TaskHandle<T>
     where
-        Fut: 'static + Future<Output = ()>,
+        Fut: 'static + Future<Output = T>,
+        T: 'static,
+    {
+        self.spawn_with_priority(Priority::Normal, task)
+    }
+
+    /// Start running <task> on this context at the given priority
+    pub fn spawn_with_priority<Fut, T>(&mut self, priority: Priority, task: Fut) -> TaskHandle<T>
+    where
+        Fut: 'static + Future<Output = T>,
+        T: 'static,
     {
         // debug!("Spawning new task");
-        self.futures.borrow().spawn_local(task).expect("");
+        let (task, abort_handle) = abortable(task);
+        let shared = Rc::new(TaskShared {
+            abort_handle,
+            state: RefCell::new(JoinState::Running(None)),
+        });
+
+        let wrapped = {
+            let shared = shared.clone();
+            async move {
+                if let Ok(output) = task.await {
+                    let previous = shared.state.replace(JoinState::Finished(output));
+                    if let JoinState::Running(Some(waker)) = previous {
+                        waker.wake()
+                    }
+                }
+            }
+        };
+        let budgeted = {
+            let budget = self.budget.clone();
+            let mut wrapped = Box::pin(wrapped);
+            poll_fn(move |cx| {
+                if budget.borrow_mut().take_poll() {
+                    wrapped.as_mut().poll(cx)
+                } else {
+                    // out of budget, try again on the next run
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+        };
+        let budgeted: Pin<Box<dyn Future<Output = ()>>> = Box::pin(budgeted);
+        self.spawned
+            .borrow_mut()
+            .push((priority, LocalFutureObj::new(budgeted)));
         if let Some(waker) = self.task_waker.replace(None) {
             waker.wake();
         }
+
+        TaskHandle { shared }
+    }
+
+    /// Create a scope whose tasks are all aborted together, for example
+    /// the tasks belonging to one room or scene
+    pub fn scope(&self) -> TaskScope<'a, E> {
+        TaskScope {
+            task_context: self.clone(),
+            tasks: Vec::new(),
+        }
     }
 
     pub fn dispatch(&self, event: E) {
@@ -81,4 +326,191 @@ impl<'a, E> TaskContext<'a, E> {
     pub fn drain(&self) -> Vec<E> {
         self.events.replace(Vec::new())
     }
+
+    pub fn bus(&self) -> &EventBus {
+        &self.bus
+    }
+
+    /// Publish <event> on the bus channel for its type
+    pub fn publish<T: Clone + 'static>(&self, event: T) -> Result<(), Full<T>> {
+        self.bus.publish(event)
+    }
+
+    /// Subscribe to the bus channel for events of type `T`
+    pub fn subscribe<T: 'static>(&self) -> Subscriber<T> {
+        self.bus.subscribe()
+    }
+}
+
+/// Why awaiting a `TaskHandle` didn't produce the task's output
+#[derive(Debug, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted before it finished
+    Aborted,
+    /// The output was already taken by an earlier await
+    Taken,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Aborted => write!(f, "task was aborted"),
+            JoinError::Taken => write!(f, "task output was already taken"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+enum JoinState<T> {
+    Running(Option<Waker>),
+    Finished(T),
+    Aborted,
+    Taken,
+}
+
+struct TaskShared<T> {
+    abort_handle: AbortHandle,
+    state: RefCell<JoinState<T>>,
+}
+
+// lets a TaskScope hold tasks of any output type
+trait AbortTask {
+    fn abort(&self);
+    fn is_finished(&self) -> bool;
+}
+
+impl<T> AbortTask for TaskShared<T> {
+    fn abort(&self) {
+        let state: &mut JoinState<T> = &mut self.state.borrow_mut();
+        if let JoinState::Running(waker) = state {
+            self.abort_handle.abort();
+            if let Some(waker) = waker.take() {
+                waker.wake()
+            }
+            *state = JoinState::Aborted;
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        !matches!(*self.state.borrow(), JoinState::Running(_))
+    }
+}
+
+/// A handle to a task spawned on a `TaskContext`
+///
+/// Awaiting the handle yields the task's output, or `JoinError::Aborted`
+/// if the task was aborted first.
+pub struct TaskHandle<T> {
+    shared: Rc<TaskShared<T>>,
+}
+
+impl<T> TaskHandle<T> {
+    /// Stop the task; it is dropped the next time the context runs
+    ///
+    /// Has no effect if the task already finished.
+    pub fn abort(&self) {
+        self.shared.abort()
+    }
+
+    /// Whether the task has finished or been aborted
+    pub fn is_finished(&self) -> bool {
+        self.shared.is_finished()
+    }
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let state: &mut JoinState<T> = &mut self.shared.state.borrow_mut();
+        match std::mem::replace(state, JoinState::Taken) {
+            JoinState::Running(_) => {
+                *state = JoinState::Running(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+            JoinState::Finished(output) => Poll::Ready(Ok(output)),
+            JoinState::Aborted => {
+                *state = JoinState::Aborted;
+                Poll::Ready(Err(JoinError::Aborted))
+            }
+            JoinState::Taken => Poll::Ready(Err(JoinError::Taken)),
+        }
+    }
+}
+
+/// A group of tasks that are aborted together
+///
+/// Every task spawned through the scope is aborted by `abort_all`, or
+/// when the scope is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::task_context::TaskContext;
+/// # async fn room_ambience() {}
+/// # async fn room_npc_chatter() {}
+/// let task_context: TaskContext<()> = TaskContext::new();
+/// let mut room_scope = task_context.scope();
+/// room_scope.spawn(room_ambience());
+/// room_scope.spawn(room_npc_chatter());
+///
+/// // leaving the room
+/// drop(room_scope);
+/// ```
+pub struct TaskScope<'a, E> {
+    task_context: TaskContext<'a, E>,
+    tasks: Vec<Rc<dyn AbortTask>>,
+}
+
+impl<'a, E> TaskScope<'a, E> {
+    pub fn spawn<Fut, T>(&mut self, task: Fut) -> TaskHandle<T>
+    where
+        Fut: 'static + Future<Output = T>,
+        T: 'static,
+    {
+        self.spawn_with_priority(Priority::Normal, task)
+    }
+
+    pub fn spawn_with_priority<Fut, T>(&mut self, priority: Priority, task: Fut) -> TaskHandle<T>
+    where
+        Fut: 'static + Future<Output = T>,
+        T: 'static,
+    {
+        self.tasks.retain(|task| !task.is_finished());
+        let handle = self.task_context.spawn_with_priority(priority, task);
+        self.tasks.push(handle.shared.clone());
+        handle
+    }
+
+    /// Abort every task spawned through this scope so far
+    pub fn abort_all(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort()
+        }
+    }
+}
+
+impl<'a, E> Drop for TaskScope<'a, E> {
+    fn drop(&mut self) {
+        self.abort_all()
+    }
+}
+
+/// Let the other ready tasks run before continuing
+///
+/// Long-running tasks should call this between chunks of work so that a
+/// budgeted run can stop at the chunk boundary.
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(move |cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
 }
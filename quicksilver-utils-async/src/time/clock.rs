@@ -0,0 +1,208 @@
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::desktop::time as platform;
+
+#[cfg(all(target_arch = "wasm32", feature = "stdweb"))]
+use crate::std_web::time as platform;
+
+#[cfg(all(target_arch = "wasm32", feature = "web-sys"))]
+use crate::web_sys::time as platform;
+
+use super::Instant;
+
+/// A source of time for `Instant::now` and every timer in this crate
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// A future that completes once this clock reaches <deadline>
+    fn sleep_until(&self, deadline: Instant) -> LocalBoxFuture<'static, ()>;
+}
+
+/// The real clock of the platform, used unless another clock is set
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant(platform::now())
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> LocalBoxFuture<'static, ()> {
+        async move {
+            loop {
+                let remaining = deadline.duration_since(SystemClock.now());
+                if remaining == Duration::from_secs(0) {
+                    break;
+                }
+                // round up, the platform timers only have millisecond resolution
+                let ms = remaining.as_micros().div_ceil(1000);
+                platform::sleep_ms(ms.min(u32::MAX as u128) as u32).await
+            }
+        }
+        .boxed_local()
+    }
+}
+
+struct ManualClockInner {
+    now: Instant,
+    /// The deadline and waker of each pending timer, by id
+    sleepers: BTreeMap<u64, (Instant, Waker)>,
+    next_id: u64,
+}
+
+/// A clock that only moves when told to
+///
+/// Timers waiting on a `ManualClock` are woken by `advance`, which
+/// makes time-based tasks testable without real sleeps.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::time::{set_clock, ManualClock};
+/// # use std::time::Duration;
+/// let clock = ManualClock::new();
+/// let _guard = set_clock(clock.clone());
+/// // every timer set from here on waits for this
+/// clock.advance(Duration::from_millis(500));
+/// ```
+#[derive(Clone)]
+pub struct ManualClock {
+    inner: Rc<RefCell<ManualClockInner>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock {
+            inner: Rc::new(RefCell::new(ManualClockInner {
+                now: Instant(Duration::from_secs(0)),
+                sleepers: BTreeMap::new(),
+                next_id: 0,
+            })),
+        }
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock::default()
+    }
+
+    /// Move the clock forward, waking every timer whose deadline has passed
+    pub fn advance(&self, duration: Duration) {
+        let mut woken: Vec<Waker> = Vec::new();
+        {
+            let inner: &mut ManualClockInner = &mut self.inner.borrow_mut();
+            inner.now += duration;
+            let now = inner.now;
+            inner.sleepers.retain(|_, (deadline, waker)| {
+                if *deadline <= now {
+                    woken.push(waker.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        for waker in woken {
+            waker.wake()
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.inner.borrow().now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> LocalBoxFuture<'static, ()> {
+        let id = {
+            let inner: &mut ManualClockInner = &mut self.inner.borrow_mut();
+            inner.next_id += 1;
+            inner.next_id
+        };
+        ManualSleep {
+            inner: self.inner.clone(),
+            id,
+            deadline,
+        }
+        .boxed_local()
+    }
+}
+
+/// A timer on a `ManualClock`, which keeps a single slot for its waker
+/// and gives it up when dropped
+struct ManualSleep {
+    inner: Rc<RefCell<ManualClockInner>>,
+    id: u64,
+    deadline: Instant,
+}
+
+impl Future for ManualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let inner: &mut ManualClockInner = &mut self.inner.borrow_mut();
+        if inner.now >= self.deadline {
+            inner.sleepers.remove(&self.id);
+            Poll::Ready(())
+        } else {
+            inner
+                .sleepers
+                .insert(self.id, (self.deadline, cx.waker().clone()));
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for ManualSleep {
+    fn drop(&mut self) {
+        // given up on, as by the losing side of a `select` or `timeout`
+        self.inner.borrow_mut().sleepers.remove(&self.id);
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Rc<dyn Clock>> = RefCell::new(Rc::new(SystemClock));
+}
+
+/// Restores the previous clock when dropped
+#[must_use = "the clock is restored as soon as the guard is dropped"]
+pub struct ClockGuard {
+    previous: Option<Rc<dyn Clock>>,
+}
+
+impl Drop for ClockGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            CURRENT.with(|current| current.replace(previous));
+        }
+    }
+}
+
+/// Use <clock> for every timer on this thread until the guard is dropped
+pub fn set_clock<C: Clock + 'static>(clock: C) -> ClockGuard {
+    let previous = CURRENT.with(|current| current.replace(Rc::new(clock)));
+    ClockGuard {
+        previous: Some(previous),
+    }
+}
+
+/// Time since the Unix epoch, from the wall clock of the platform
+///
+/// Unlike `Instant`, this can be compared across runs, but it isn't
+/// monotonic and no `Clock` controls it.
+pub(crate) fn unix_time() -> Duration {
+    platform::unix_time()
+}
+
+pub(super) fn current() -> Rc<dyn Clock> {
+    CURRENT.with(|current| current.borrow().clone())
+}
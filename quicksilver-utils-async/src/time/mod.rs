@@ -0,0 +1,304 @@
+//! # time
+//!
+//! Timers that behave the same on desktop and on the web. `sleep`
+//! and `sleep_until` suspend a task, `interval` produces a stream of
+//! ticks that doesn't drift, and `timeout` races a future against a
+//! deadline.
+//!
+//! Deadlines are expressed with this module's `Instant` rather than
+//! `std::time::Instant`, which isn't available on wasm32. Both come
+//! from the current `Clock`, which is the platform clock unless a
+//! `ManualClock` has been set for testing.
+
+use futures_util::{
+    future::{select, Either, LocalBoxFuture},
+    pin_mut,
+    stream::{Stream, StreamExt},
+};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+mod clock;
+
+pub(crate) use clock::unix_time;
+pub use clock::{set_clock, Clock, ClockGuard, ManualClock, SystemClock};
+
+/// A point in time, measured by the current `Clock`
+///
+/// Only meaningful relative to other `Instant`s from the same run of
+/// the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    pub fn now() -> Instant {
+        clock::current().now()
+    }
+
+    /// The time elapsed from `earlier` to `self`, or zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier)
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0 + duration)
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        self.0 += duration
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        Instant(self.0 - duration)
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        self.0 -= duration
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Block the async task until woken by the system after <ms> milliseconds
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::time::sleep_ms;
+/// # fn do_something_periodically() {}
+/// async fn tick_loop() {
+///     loop {
+///         sleep_ms(500).await;
+///         do_something_periodically()
+///     }
+/// }
+/// ```
+pub async fn sleep_ms(ms: u32) {
+    sleep(Duration::from_millis(ms as u64)).await
+}
+
+/// Block the async task until <duration> has passed
+pub async fn sleep(duration: Duration) {
+    sleep_until(Instant::now() + duration).await
+}
+
+/// Block the async task until the clock reaches <deadline>
+///
+/// Returns immediately if the deadline is already in the past.
+pub async fn sleep_until(deadline: Instant) {
+    let delay = clock::current().sleep_until(deadline);
+    delay.await
+}
+
+/// What an `Interval` does when ticks are missed because the task
+/// wasn't polled in time
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::time::{interval, MissedTickBehavior};
+/// # use std::time::Duration;
+/// let mut ticks = interval(Duration::from_millis(500));
+/// ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Fire the missed ticks back-to-back until caught up with the schedule
+    #[default]
+    Burst,
+    /// Fire once, then restart the schedule a full period from now
+    Delay,
+    /// Fire once, then wait for the next tick that is still on the schedule
+    Skip,
+}
+
+/// A stream of ticks, one every `period`
+///
+/// Each tick is scheduled relative to the previous deadline rather
+/// than to when the task got around to polling, so the ticks don't
+/// drift. The item is the deadline the tick was scheduled for.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::time::{interval, MissedTickBehavior};
+/// # use std::time::Duration;
+/// # fn do_something_periodically() {}
+/// async fn tick_loop() {
+///     let mut ticks = interval(Duration::from_millis(500));
+///     ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+///     loop {
+///         ticks.tick().await;
+///         do_something_periodically()
+///     }
+/// }
+/// ```
+pub struct Interval {
+    next: Instant,
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+    delay: Option<LocalBoxFuture<'static, ()>>,
+}
+
+/// Create an `Interval` whose first tick completes immediately
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Instant::now(), period)
+}
+
+/// Create an `Interval` whose first tick completes at <start>
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(
+        period > Duration::from_secs(0),
+        "interval period must be non-zero"
+    );
+    Interval {
+        next: start,
+        period,
+        missed_tick_behavior: MissedTickBehavior::default(),
+        delay: None,
+    }
+}
+
+impl Interval {
+    /// Wait for the next tick, returning the deadline it was scheduled for
+    pub async fn tick(&mut self) -> Instant {
+        self.next()
+            .await
+            .expect("an interval never runs out of ticks")
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior
+    }
+
+    /// Restart the schedule so that the next tick is a full period from now
+    pub fn reset(&mut self) {
+        self.next = Instant::now() + self.period;
+        self.delay = None;
+    }
+
+    fn schedule_after(&self, tick: Instant, now: Instant) -> Instant {
+        let on_schedule = tick + self.period;
+        if on_schedule > now {
+            return on_schedule;
+        }
+        match self.missed_tick_behavior {
+            MissedTickBehavior::Burst => on_schedule,
+            MissedTickBehavior::Delay => now + self.period,
+            MissedTickBehavior::Skip => {
+                // the rest of the period now is in, however many were missed
+                let into_period = (now - tick).as_nanos() % self.period.as_nanos();
+                now + self.period - Duration::from_nanos(into_period as u64)
+            }
+        }
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Instant>> {
+        if Instant::now() < self.next {
+            let next = self.next;
+            let delay = self
+                .delay
+                .get_or_insert_with(|| Box::pin(sleep_until(next)));
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        let tick = self.next;
+        self.next = self.schedule_after(tick, Instant::now());
+        self.delay = None;
+        Poll::Ready(Some(tick))
+    }
+}
+
+/// The error returned by `timeout` when the deadline passes first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl Error for Elapsed {}
+
+/// Run <future> to completion, giving up after <duration>
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::time::{sleep_ms, timeout};
+/// # use std::time::Duration;
+/// async fn impatient() {
+///     if timeout(Duration::from_millis(100), sleep_ms(5000)).await.is_err() {
+///         println!("gave up");
+///     }
+/// }
+/// ```
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    timeout_at(Instant::now() + duration, future).await
+}
+
+/// Run <future> to completion, giving up once the clock reaches <deadline>
+pub async fn timeout_at<F: Future>(deadline: Instant, future: F) -> Result<F::Output, Elapsed> {
+    let delay = sleep_until(deadline);
+    pin_mut!(future);
+    pin_mut!(delay);
+    match select(future, delay).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(Elapsed(())),
+    }
+}
@@ -0,0 +1,55 @@
+use crate::request::{from_hex, to_hex, CacheStorage};
+use log::warn;
+use std::sync::Once;
+use web_sys::Storage;
+
+/// Keeps cached responses in the browser's localStorage, under keys
+/// starting with a prefix
+///
+/// localStorage only holds strings, so entries are stored as hex.
+#[derive(Clone, Debug)]
+pub struct LocalStorage {
+    prefix: String,
+}
+
+impl LocalStorage {
+    pub fn new(prefix: &str) -> Self {
+        LocalStorage {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn storage(&self) -> Option<Storage> {
+        let storage = web_sys::window()?.local_storage().ok().flatten();
+        if storage.is_none() {
+            static UNAVAILABLE: Once = Once::new();
+            UNAVAILABLE
+                .call_once(|| warn!("localStorage is not available, responses won't be kept"));
+        }
+        storage
+    }
+}
+
+impl CacheStorage for LocalStorage {
+    fn load(&self, key: &str) -> Option<Vec<u8>> {
+        let hex = self
+            .storage()?
+            .get_item(&format!("{}{}", self.prefix, key))
+            .ok()??;
+        from_hex(&hex)
+    }
+
+    fn store(&self, key: &str, entry: &[u8]) {
+        if let Some(storage) = self.storage() {
+            if let Err(e) = storage.set_item(&format!("{}{}", self.prefix, key), &to_hex(entry)) {
+                warn!("Failed caching {}: {:?}", key, e);
+            }
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        if let Some(storage) = self.storage() {
+            let _ = storage.remove_item(&format!("{}{}", self.prefix, key));
+        }
+    }
+}
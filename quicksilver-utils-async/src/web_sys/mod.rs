@@ -1,8 +1,8 @@
 extern crate js_sys;
-extern crate surf;
 extern crate wasm_bindgen;
 extern crate web_sys;
 
+pub(crate) mod cache;
 pub(crate) mod request;
 pub(crate) mod time;
 pub(crate) mod websocket;
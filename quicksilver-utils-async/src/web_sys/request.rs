@@ -1,43 +1,241 @@
-use crate::request::{RequestError, Result, ServiceClient};
+use js_sys::{Promise, Reflect, Uint8Array};
+use web_sys::{
+    AbortController, Headers, ReadableStreamDefaultReader, RequestCredentials, RequestInit,
+    RequestMode,
+};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use std::task::{Poll, Waker};
+
+use futures_util::future::poll_fn;
+use futures_util::stream::{self, StreamExt};
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+
 use async_trait::async_trait;
 use bytes::Bytes;
-use http::Uri;
 
-// TODO: this file is identical to desktop; clean up the package switching?
+use crate::request::{
+    build_response, HttpTransport, Request, RequestError, Result, StreamingResponse,
+};
+
+use log::trace;
 
-pub struct ServiceClientImpl {
-    auth_token: Option<String>,
+fn js_error(context: &str, js_value: JsValue) -> RequestError {
+    let message = js_value
+        .as_string()
+        .or_else(|| {
+            js_value
+                .dyn_ref::<js_sys::Error>()
+                .map(|e| String::from(e.message()))
+        })
+        .unwrap_or_else(|| format!("{:?}", js_value));
+    RequestError::Transport(format!("{}: {}", context, message))
 }
 
-#[async_trait]
-impl ServiceClient for ServiceClientImpl {
-    // TODO: build a surf client once and re-use?
-    fn new() -> Self {
-        let auth_token = None;
-        ServiceClientImpl { auth_token }
+type Callback = Closure<dyn FnMut(JsValue)>;
+
+struct PromiseInner {
+    result: Option<std::result::Result<JsValue, JsValue>>,
+    waker: Option<Waker>,
+    // kept alive until the promise settles, then dropped from inside the callback
+    callbacks: Option<(Callback, Callback)>,
+}
+
+/// Wait for <promise> to resolve or reject
+async fn settled(promise: Promise) -> std::result::Result<JsValue, JsValue> {
+    let inner = Rc::new(RefCell::new(PromiseInner {
+        result: None,
+        waker: None,
+        callbacks: None,
+    }));
+
+    let callback = |resolved: bool| {
+        let inner = inner.clone();
+        Closure::wrap(Box::new(move |value: JsValue| {
+            trace!("Promise settled callback!");
+            let callbacks = {
+                let inner: &mut PromiseInner = &mut inner.borrow_mut();
+                inner.result = Some(if resolved { Ok(value) } else { Err(value) });
+                if let Some(waker) = inner.waker.take() {
+                    waker.wake()
+                }
+                inner.callbacks.take()
+            };
+            drop(callbacks);
+        }) as Box<dyn FnMut(JsValue)>)
+    };
+    let on_resolve = callback(true);
+    let on_reject = callback(false);
+    let _ = promise.then2(&on_resolve, &on_reject);
+    inner.borrow_mut().callbacks = Some((on_resolve, on_reject));
+
+    poll_fn(move |cx| {
+        trace!("Polling");
+        let inner: &mut PromiseInner = &mut inner.borrow_mut();
+        match inner.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                inner.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
+
+/// Aborts the fetch when dropped before it is disarmed, so dropping a
+/// request future or body stream cancels it
+struct AbortOnDrop(Option<AbortController>);
+
+impl AbortOnDrop {
+    fn disarm(&mut self) {
+        self.0.take();
     }
+}
 
-    fn set_auth_token(&mut self, auth_token: &str) {
-        self.auth_token = Some(auth_token.to_string())
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        if let Some(controller) = self.0.take() {
+            trace!("Aborting fetch");
+            controller.abort()
+        }
     }
+}
 
-    async fn post_raw(&self, uri: Uri, request_body: Bytes) -> Result<Bytes> {
-        let raw_uri = format!("{}", uri);
-        let mut request = surf::post(raw_uri)
-            .set_header("Accept", "application/octet-stream")
-            .set_header("Content-Type", "application/octet-stream");
+/// Which origins a `FetchTransport` may send requests to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FetchMode {
+    /// Any origin, subject to CORS
+    #[default]
+    Cors,
+    /// Any origin, but cross-origin responses are opaque
+    NoCors,
+    /// Only the page's own origin
+    SameOrigin,
+}
 
-        if let Some(auth_token) = &self.auth_token {
-            request = request.set_header("Authorization", auth_token);
+/// When a `FetchTransport` sends cookies and HTTP auth
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FetchCredentials {
+    Omit,
+    /// Only to the page's own origin
+    #[default]
+    SameOrigin,
+    Include,
+}
+
+/// Sends requests with the browser's `fetch`
+///
+/// Dropping the request future or the body stream aborts the fetch.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FetchTransport {
+    mode: FetchMode,
+    credentials: FetchCredentials,
+}
+
+impl FetchTransport {
+    pub fn new() -> Self {
+        FetchTransport::default()
+    }
+
+    pub fn set_mode(&mut self, mode: FetchMode) {
+        self.mode = mode
+    }
+
+    pub fn set_credentials(&mut self, credentials: FetchCredentials) {
+        self.credentials = credentials
+    }
+}
+
+#[async_trait(?Send)]
+impl HttpTransport for FetchTransport {
+    async fn send(&self, request: Request) -> Result<StreamingResponse> {
+        let (parts, body) = request.into_parts();
+        let window =
+            web_sys::window().ok_or_else(|| RequestError::Transport("No window".to_string()))?;
+        let controller = AbortController::new().map_err(|e| js_error("AbortController", e))?;
+
+        let headers = Headers::new().map_err(|e| js_error("Headers", e))?;
+        for (name, value) in parts.headers.iter() {
+            let value = value
+                .to_str()
+                .map_err(|e| RequestError::Encode(format!("Invalid header {}", e)))?;
+            headers
+                .append(name.as_str(), value)
+                .map_err(|e| js_error("Set Header", e))?;
         }
 
-        request = request.body_bytes(request_body);
+        let init = RequestInit::new();
+        init.set_method(parts.method.as_str());
+        init.set_headers(&headers);
+        init.set_signal(Some(&controller.signal()));
+        init.set_mode(match self.mode {
+            FetchMode::Cors => RequestMode::Cors,
+            FetchMode::NoCors => RequestMode::NoCors,
+            FetchMode::SameOrigin => RequestMode::SameOrigin,
+        });
+        init.set_credentials(match self.credentials {
+            FetchCredentials::Omit => RequestCredentials::Omit,
+            FetchCredentials::SameOrigin => RequestCredentials::SameOrigin,
+            FetchCredentials::Include => RequestCredentials::Include,
+        });
+        if !body.is_empty() {
+            init.set_body(&Uint8Array::from(&body[..]));
+        }
 
-        let response_bytes_vec = request
-            .recv_bytes()
+        let url = format!("{}", parts.uri);
+        let fetch_request = web_sys::Request::new_with_str_and_init(&url, &init)
+            .map_err(|e| RequestError::Encode(format!("Invalid request {:?}", e)))?;
+
+        let mut abort = AbortOnDrop(Some(controller));
+        let response: web_sys::Response = settled(window.fetch_with_request(&fetch_request))
             .await
-            .map_err(|e| RequestError::NativeError(format!("Failed making hyper request {}", e)))?;
+            .map_err(|e| js_error("Fetch", e))?
+            .dyn_into()
+            .map_err(|e| js_error("Fetch response", e))?;
+
+        let mut response_headers = Vec::new();
+        if let Some(entries) =
+            js_sys::try_iter(&response.headers()).map_err(|e| js_error("Headers", e))?
+        {
+            for entry in entries {
+                let entry: js_sys::Array = entry.map_err(|e| js_error("Headers", e))?.into();
+                let name = entry.get(0).as_string().unwrap_or_default();
+                let value = entry.get(1).as_string().unwrap_or_default();
+                response_headers.push((name, value));
+            }
+        }
+
+        let reader: Option<ReadableStreamDefaultReader> = response
+            .body()
+            .map(|body| body.get_reader().unchecked_into());
+        if reader.is_none() {
+            abort.disarm();
+        }
+
+        let body = stream::unfold((reader, abort), |(reader, mut abort)| async move {
+            let reader = reader?;
+            let chunk = match settled(reader.read()).await {
+                Ok(chunk) => chunk,
+                Err(e) => return Some((Err(js_error("Read body", e)), (None, abort))),
+            };
+            let done = Reflect::get(&chunk, &JsValue::from_str("done"))
+                .ok()
+                .and_then(|done| done.as_bool())
+                .unwrap_or(true);
+            if done {
+                abort.disarm();
+                return None;
+            }
+            let value =
+                Reflect::get(&chunk, &JsValue::from_str("value")).unwrap_or(JsValue::UNDEFINED);
+            let bytes = Bytes::from(Uint8Array::new(&value).to_vec());
+            Some((Ok(bytes), (Some(reader), abort)))
+        });
 
-        Ok(Bytes::from(response_bytes_vec))
+        build_response(response.status(), response_headers, body.boxed_local())
     }
 }
@@ -1,6 +1,7 @@
 use std::cell::RefCell;
-use std::sync::Arc;
+use std::rc::Rc;
 use std::task::{Poll, Waker};
+use std::time::Duration;
 
 use wasm_bindgen::prelude::{Closure, JsValue};
 use wasm_bindgen::JsCast;
@@ -16,10 +17,38 @@ struct ReadyWaker {
     waker: Option<Waker>,
 }
 
+/// A `setTimeout` that is cleared, and its callback freed, when dropped
+struct Timeout {
+    handle: i32,
+    _callback: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Drop for Timeout {
+    fn drop(&mut self) {
+        // harmless once it fired
+        if let Some(window) = window() {
+            window.clear_timeout_with_handle(self.handle)
+        }
+    }
+}
+
+/// Time since the page loaded, from the monotonic `performance.now()`
+pub fn now() -> Duration {
+    let performance = window()
+        .and_then(|window| window.performance())
+        .expect("Get the performance timer");
+    Duration::from_secs_f64(performance.now() / 1000.)
+}
+
+/// Time since the Unix epoch, from the wall clock
+pub fn unix_time() -> Duration {
+    Duration::from_millis(js_sys::Date::now() as u64)
+}
+
 pub async fn sleep_ms(ms: u32) {
     let window = window().expect("Get the window");
 
-    let ready_waker = Arc::new(RefCell::new(ReadyWaker {
+    let ready_waker = Rc::new(RefCell::new(ReadyWaker {
         ready: false,
         waker: None,
     }));
@@ -36,14 +65,17 @@ pub async fn sleep_ms(ms: u32) {
         }) as Box<dyn FnMut(JsValue)>)
     };
 
-    window
+    let handle = window
         .set_timeout_with_callback_and_timeout_and_arguments(
             callback.as_ref().unchecked_ref(),
             ms as i32,
             &Array::new(),
         )
         .expect("Invoke set_timeout");
-    callback.forget();
+    let _timeout = Timeout {
+        handle,
+        _callback: callback,
+    };
 
     poll_fn({
         let ready_waker = ready_waker.clone();
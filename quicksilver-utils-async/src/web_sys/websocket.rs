@@ -1,8 +1,8 @@
-use js_sys::{ArrayBuffer, Uint8Array};
-use web_sys::{BinaryType, MessageEvent, WebSocket};
+use js_sys::{Array, ArrayBuffer, Uint8Array};
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
 
 use std::cell::RefCell;
-use std::sync::Arc;
+use std::rc::Rc;
 
 use std::collections::VecDeque;
 
@@ -15,7 +15,8 @@ use wasm_bindgen::JsCast;
 
 use bytes::Bytes;
 
-use crate::websocket::{WebSocketError, WebSocketMessage};
+use crate::time::Instant;
+use crate::websocket::{within, WebSocketConfig, WebSocketError, WebSocketMessage};
 
 use log::trace;
 
@@ -35,12 +36,30 @@ enum SocketState {
 struct AsyncWebSocketInner {
     ws: WebSocket,
     state: SocketState,
-    waker: Option<Waker>,
+    /// Every task waiting on the socket, since clones and split halves
+    /// wait on it at the same time
+    wakers: Vec<Waker>,
     buffer: VecDeque<MessageEvent>,
+    /// The close event, until received after the buffered messages
+    close: Option<WebSocketMessage>,
+}
+
+impl AsyncWebSocketInner {
+    fn wait(&mut self, waker: &Waker) {
+        if !self.wakers.iter().any(|waiting| waiting.will_wake(waker)) {
+            self.wakers.push(waker.clone());
+        }
+    }
+
+    fn wake_all(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake()
+        }
+    }
 }
 
 pub struct AsyncWebSocket {
-    inner: Arc<RefCell<AsyncWebSocketInner>>,
+    inner: Rc<RefCell<AsyncWebSocketInner>>,
 }
 
 impl Clone for AsyncWebSocket {
@@ -52,20 +71,30 @@ impl Clone for AsyncWebSocket {
 }
 
 impl AsyncWebSocket {
-    pub async fn connect(url: &Url) -> Result<Self, WebSocketError> {
-        let ws = WebSocket::new(url.as_str())?;
+    pub async fn connect(url: &Url, config: &WebSocketConfig) -> Result<Self, WebSocketError> {
+        let ws = if config.protocols.is_empty() {
+            WebSocket::new(url.as_str())?
+        } else {
+            let protocols: Array = config
+                .protocols
+                .iter()
+                .map(|p| JsValue::from_str(p))
+                .collect();
+            WebSocket::new_with_str_sequence(url.as_str(), &protocols)?
+        };
         ws.set_binary_type(BinaryType::Arraybuffer);
         let async_ws: AsyncWebSocket = {
             let ws = ws.clone();
             let state = SocketState::Init;
-            let waker = None;
+            let wakers = Vec::new();
             let buffer = VecDeque::new();
 
-            let inner = Arc::new(RefCell::new(AsyncWebSocketInner {
+            let inner = Rc::new(RefCell::new(AsyncWebSocketInner {
                 ws,
                 state,
-                waker,
+                wakers,
                 buffer,
+                close: None,
             }));
             AsyncWebSocket { inner }
         };
@@ -76,9 +105,7 @@ impl AsyncWebSocket {
                 trace!("Websocket onopen callback!");
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 inner.state = SocketState::Open;
-                if let Some(waker) = inner.waker.take() {
-                    waker.wake()
-                }
+                inner.wake_all();
             }) as Box<dyn FnMut(JsValue)>)
         };
         ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
@@ -86,14 +113,16 @@ impl AsyncWebSocket {
 
         let onclose_callback = {
             let async_ws = async_ws.clone();
-            Closure::wrap(Box::new(move |_| {
+            Closure::wrap(Box::new(move |ev: CloseEvent| {
                 trace!("Websocket onclose callback!");
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 inner.state = SocketState::Closed;
-                if let Some(waker) = inner.waker.take() {
-                    waker.wake()
-                }
-            }) as Box<dyn FnMut(JsValue)>)
+                inner.close = Some(WebSocketMessage::Close {
+                    code: ev.code(),
+                    reason: ev.reason(),
+                });
+                inner.wake_all();
+            }) as Box<dyn FnMut(CloseEvent)>)
         };
         ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
         onclose_callback.forget();
@@ -104,9 +133,7 @@ impl AsyncWebSocket {
                 trace!("Websocket onerror callback!");
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 inner.state = SocketState::Error(err.as_string().unwrap());
-                if let Some(waker) = inner.waker.take() {
-                    waker.wake()
-                }
+                inner.wake_all();
             }) as Box<dyn FnMut(JsValue)>)
         };
         ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
@@ -118,22 +145,20 @@ impl AsyncWebSocket {
                 trace!("Websocket onmessage callback!");
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 inner.buffer.push_back(ev);
-                if let Some(waker) = inner.waker.take() {
-                    waker.wake()
-                }
+                inner.wake_all();
             }) as Box<dyn FnMut(MessageEvent)>)
         };
         ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
         onmessage_callback.forget();
 
-        poll_fn({
+        let opened = poll_fn({
             let async_ws = async_ws.clone();
             move |cx| {
                 trace!("Polling");
                 let inner: &mut AsyncWebSocketInner = &mut *async_ws.inner.borrow_mut();
                 match &inner.state {
                     SocketState::Init => {
-                        inner.waker.replace(cx.waker().clone());
+                        inner.wait(cx.waker());
                         Poll::Pending
                     }
                     SocketState::Open => Poll::Ready(Ok(())),
@@ -143,22 +168,47 @@ impl AsyncWebSocket {
                     SocketState::Closed => Poll::Ready(Err(WebSocketError::StateClosed)),
                 }
             }
-        })
-        .await?;
+        });
+        if let Err(e) = within(config.open_timeout(), opened).await {
+            let _ = ws.close();
+            return Err(e);
+        }
 
         Ok(async_ws)
     }
 
+    pub fn protocol(&self) -> Option<String> {
+        let protocol = self.inner.borrow().ws.protocol();
+        if protocol.is_empty() {
+            None
+        } else {
+            Some(protocol)
+        }
+    }
+
     pub async fn send(&self, msg: &WebSocketMessage) -> Result<(), WebSocketError> {
         trace!("Send");
         let inner: &mut AsyncWebSocketInner = &mut *self.inner.borrow_mut();
         match msg {
             WebSocketMessage::String(s) => inner.ws.send_with_str(s)?,
             WebSocketMessage::Binary(b) => inner.ws.send_with_u8_array(b)?,
+            WebSocketMessage::Close { code, reason } => {
+                inner.ws.close_with_code_and_reason(*code, reason)?
+            }
         }
         Ok(())
     }
 
+    /// Send <message>, browsers don't expose ping frames
+    pub async fn ping(&self, _id: u64, message: &WebSocketMessage) -> Result<(), WebSocketError> {
+        self.send(message).await
+    }
+
+    /// Always `None`, the answer to a ping is a message here
+    pub fn last_pong(&self) -> Option<(u64, Instant)> {
+        None
+    }
+
     pub async fn close(&self) -> Result<(), WebSocketError> {
         let inner: &mut AsyncWebSocketInner = &mut *self.inner.borrow_mut();
         inner.ws.close()?;
@@ -166,42 +216,43 @@ impl AsyncWebSocket {
     }
 
     pub async fn receive(&self) -> Result<WebSocketMessage, WebSocketError> {
-        let message_event = poll_fn({
+        poll_fn({
             move |cx| {
                 trace!("Polling");
                 let inner: &mut AsyncWebSocketInner = &mut *self.inner.borrow_mut();
+                if let Some(ev) = inner.buffer.pop_front() {
+                    return Poll::Ready(Ok(message(ev)));
+                }
                 match &inner.state {
                     SocketState::Init => Poll::Ready(Err(WebSocketError::StateInit)),
                     SocketState::Open => {
-                        if let Some(ev) = inner.buffer.pop_front() {
-                            Poll::Ready(Ok(ev))
-                        } else {
-                            inner.waker.replace(cx.waker().clone());
-                            Poll::Pending
-                        }
+                        inner.wait(cx.waker());
+                        Poll::Pending
                     }
                     SocketState::Error(val) => {
                         Poll::Ready(Err(WebSocketError::StateError(val.clone())))
                     }
-                    SocketState::Closed => Poll::Ready(Err(WebSocketError::StateClosed)),
+                    SocketState::Closed => {
+                        Poll::Ready(inner.close.take().ok_or(WebSocketError::StateClosed))
+                    }
                 }
             }
         })
-        .await?;
-
-        let data: JsValue = message_event.data();
-        trace!("{:?}", &data);
-
-        let message = match data.as_string() {
-            Some(s) => WebSocketMessage::String(s),
-            None => {
-                let buf: &ArrayBuffer = data.as_ref().unchecked_ref(); // consider using JsCast::dyn_into for safety?
-                let vec: Vec<u8> = Uint8Array::new(buf).to_vec();
-                let bytes = Bytes::from(vec);
-                WebSocketMessage::Binary(bytes)
-            }
-        };
+        .await
+    }
+}
 
-        Ok(message)
+fn message(message_event: MessageEvent) -> WebSocketMessage {
+    let data: JsValue = message_event.data();
+    trace!("{:?}", &data);
+
+    match data.as_string() {
+        Some(s) => WebSocketMessage::String(s),
+        None => {
+            let buf: &ArrayBuffer = data.as_ref().unchecked_ref(); // consider using JsCast::dyn_into for safety?
+            let vec: Vec<u8> = Uint8Array::new(buf).to_vec();
+            let bytes = Bytes::from(vec);
+            WebSocketMessage::Binary(bytes)
+        }
     }
 }
@@ -0,0 +1,144 @@
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use std::time::Duration;
+
+use super::Heartbeat;
+
+/// Options for `WebSocket::connect_with`
+///
+/// Browsers decide the handshake headers and the certificates to trust
+/// themselves, so only the subprotocols and timeouts apply on the web.
+/// There the two timeouts can't be told apart and add up.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::websocket::*;
+/// # use http::header::{HeaderValue, AUTHORIZATION};
+/// # use std::time::Duration;
+/// # use url::Url;
+/// # async fn example() -> Result<(), WebSocketError> {
+/// let config = WebSocketConfig::new()
+///     .protocol("lobby.v2")
+///     .header(AUTHORIZATION, HeaderValue::from_static("Bearer abc123"))
+///     .connect_timeout(Duration::from_secs(5))
+///     .handshake_timeout(Duration::from_secs(5));
+///
+/// let url = Url::parse("wss://example.com/lobby").unwrap();
+/// let socket = WebSocket::connect_with(&url, &config).await?;
+/// if socket.protocol().as_deref() != Some("lobby.v2") {
+///     println!("the server doesn't speak lobby.v2");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct WebSocketConfig {
+    pub(crate) protocols: Vec<String>,
+    pub(crate) headers: HeaderMap,
+    pub(crate) root_certificates: Vec<Vec<u8>>,
+    pub(crate) skip_built_in_roots: bool,
+    #[cfg(feature = "dangerous-tls")]
+    pub(crate) accept_invalid_certs: bool,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) handshake_timeout: Option<Duration>,
+    pub(crate) heartbeat: Option<Heartbeat>,
+}
+
+impl WebSocketConfig {
+    pub fn new() -> Self {
+        WebSocketConfig::default()
+    }
+
+    /// Offer <protocol> in `Sec-WebSocket-Protocol`, in order of
+    /// preference, see `WebSocket::protocol` for the one the server picked
+    pub fn protocol(mut self, protocol: &str) -> Self {
+        self.protocols.push(protocol.to_string());
+        self
+    }
+
+    /// Add a header to the handshake request, such as a cookie or a
+    /// token, on desktop only
+    ///
+    /// Connecting fails on a header the handshake sets itself, like
+    /// `Host` or `Sec-WebSocket-Protocol`, and on a value with a line
+    /// break.
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::websocket::*;
+    /// # use http::header::{HeaderValue, COOKIE};
+    /// let config = WebSocketConfig::new().header(COOKIE, HeaderValue::from_static("session=abc"));
+    /// ```
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Also trust the DER encoded root <certificate> for `wss` on desktop,
+    /// such as a self-signed one for a local server
+    pub fn add_root_certificate(mut self, certificate: impl Into<Vec<u8>>) -> Self {
+        self.root_certificates.push(certificate.into());
+        self
+    }
+
+    /// Whether to trust the platform's root certificates for `wss` on
+    /// desktop, as by default
+    ///
+    /// Without them only the certificates from `add_root_certificate`
+    /// are trusted, such as when pinning the roots of a game server.
+    ///
+    /// ```no_run
+    /// # use quicksilver_utils_async::websocket::*;
+    /// # fn example(game_server_root: Vec<u8>) {
+    /// let config = WebSocketConfig::new()
+    ///     .tls_built_in_root_certs(false)
+    ///     .add_root_certificate(game_server_root);
+    /// # }
+    /// ```
+    pub fn tls_built_in_root_certs(mut self, enabled: bool) -> Self {
+        self.skip_built_in_roots = !enabled;
+        self
+    }
+
+    /// Accept any certificate for `wss` on desktop, for tests against
+    /// servers with made up certificates only
+    ///
+    /// Only with the `dangerous-tls` feature.
+    #[cfg(feature = "dangerous-tls")]
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Give up when the server can't be reached within <timeout>,
+    /// including the TLS handshake
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Give up when the server doesn't answer the websocket handshake
+    /// within <timeout>
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Ping the server to keep the connection alive and to notice when
+    /// it died
+    pub fn heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// Both timeouts together, for the web where the browser connects
+    /// and shakes hands in one go
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn open_timeout(&self) -> Option<Duration> {
+        match (self.connect_timeout, self.handshake_timeout) {
+            (None, None) => None,
+            (connect, handshake) => {
+                Some(connect.unwrap_or_default() + handshake.unwrap_or_default())
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use futures_util::future::LocalBoxFuture;
+use futures_util::stream::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use super::{WebSocketError, WebSocketMessage};
+use crate::time::{interval_at, Instant, Interval, MissedTickBehavior};
+
+/// Pings to keep an idle connection alive, and to notice when a proxy or
+/// NAT dropped it without a word, see `WebSocketConfig::heartbeat`
+///
+/// A ping goes out every <interval>, which must be more than zero: a
+/// ping frame on desktop, and `ping_message` in the browser, which
+/// doesn't expose ping frames. Pings are numbered, and a pong only
+/// answers the ping with its number. In the browser the server has to
+/// echo the number back in a message that `pong_id` recognizes, and such
+/// messages are taken out of the received ones. Each answer updates
+/// `WebSocket::rtt`.
+///
+/// When a ping is due while more than `max_missed` are unanswered, the
+/// connection is dead, and receiving and sending fail with
+/// `WebSocketError::Timeout`. With `max_missed: 0` every ping has to be
+/// answered before the next one is due.
+///
+/// The pings are sent by a timer kept with the connection, on schedule
+/// however often messages arrive, but only while a task is receiving,
+/// which is also what reads the pongs on desktop.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::websocket::*;
+/// # use std::time::Duration;
+/// # use url::Url;
+/// # async fn example() -> Result<(), WebSocketError> {
+/// let heartbeat = Heartbeat {
+///     interval: Duration::from_secs(5),
+///     ..Heartbeat::default()
+/// };
+/// let config = WebSocketConfig::new().heartbeat(heartbeat);
+/// let url = Url::parse("wss://example.com/game").unwrap();
+/// let socket = WebSocket::connect_with(&url, &config).await?;
+/// loop {
+///     let message = socket.receive().await?;
+///     if let Some(rtt) = socket.rtt() {
+///         println!("{:?} after a {}ms round trip", message, rtt.as_millis());
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    /// Unanswered pings allowed when the next one is due
+    pub max_missed: u32,
+    /// What the browser sends as the ping with the given number, by
+    /// default `ping <number>`
+    pub ping_message: fn(u64) -> WebSocketMessage,
+    /// The number of the ping a message answers, `None` for the game's
+    /// own messages, by default reading `pong <number>`
+    pub pong_id: fn(&WebSocketMessage) -> Option<u64>,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(15),
+            max_missed: 2,
+            ping_message: |id| WebSocketMessage::String(format!("ping {}", id)),
+            pong_id: |message| match message {
+                WebSocketMessage::String(text) => text.strip_prefix("pong ")?.parse().ok(),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Where a connection's heartbeat is at, shared by its clones
+pub(crate) struct HeartbeatState {
+    pub(crate) heartbeat: Heartbeat,
+    /// The ping timer, for the life of the connection
+    ticks: Interval,
+    /// The ping being sent, finished by whichever receive polls next
+    pub(crate) pinging: Option<LocalBoxFuture<'static, Result<(), WebSocketError>>>,
+    last_id: u64,
+    /// The numbers of the unanswered pings and when they went out,
+    /// oldest first
+    unanswered: VecDeque<(u64, Instant)>,
+    rtt: Option<Duration>,
+    dead: bool,
+}
+
+impl HeartbeatState {
+    pub(crate) fn new(heartbeat: Heartbeat) -> Self {
+        let mut ticks = interval_at(Instant::now() + heartbeat.interval, heartbeat.interval);
+        // after a stall, such as a hidden browser tab, one ping is enough
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        HeartbeatState {
+            heartbeat,
+            ticks,
+            pinging: None,
+            last_id: 0,
+            unanswered: VecDeque::new(),
+            rtt: None,
+            dead: false,
+        }
+    }
+
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    pub(crate) fn check_alive(&self) -> Result<(), WebSocketError> {
+        if self.dead {
+            return Err(WebSocketError::Timeout);
+        }
+        Ok(())
+    }
+
+    /// The pong for ping <id> arrived at <at>, which also answers the
+    /// pings sent before it
+    pub(crate) fn pong(&mut self, id: u64, at: Instant) {
+        let answered = self
+            .unanswered
+            .iter()
+            .position(|(sent_id, _)| *sent_id == id);
+        if let Some(answered) = answered {
+            let (_, sent) = self.unanswered[answered];
+            self.rtt = Some(at.duration_since(sent));
+            self.unanswered.drain(..=answered);
+        }
+    }
+
+    /// Wait for the next ping to be due, and give its number, failing
+    /// once too many went unanswered
+    pub(crate) fn poll_ping(&mut self, cx: &mut Context) -> Poll<Result<u64, WebSocketError>> {
+        self.check_alive()?;
+        if Pin::new(&mut self.ticks).poll_next(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if self.unanswered.len() > self.heartbeat.max_missed as usize {
+            self.dead = true;
+            return Poll::Ready(Err(WebSocketError::Timeout));
+        }
+        self.last_id += 1;
+        self.unanswered.push_back((self.last_id, Instant::now()));
+        Poll::Ready(Ok(self.last_id))
+    }
+}
@@ -0,0 +1,389 @@
+//! # websocket
+//!
+//! An async websocket client that can send and recieve. The
+//! `WebSocket` is cloneable, so reading and writing can happen
+//! on separate futures. It is also a `Stream` of the messages it
+//! receives and a `Sink` of the messages to send, and `split` gives
+//! separate halves for reading and writing. A `ReconnectingWebSocket`
+//! keeps a connection up across drops.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use quicksilver_utils_async::websocket::*;
+//! # use futures_util::{SinkExt, StreamExt};
+//! # use url::Url;
+//! # async fn example() -> Result<(), WebSocketError> {
+//! // echo everything back, until the server closes the connection
+//! let url = Url::parse("wss://echo.example.com").unwrap();
+//! let (writer, reader) = WebSocket::connect(&url).await?.split();
+//! reader.forward(writer).await?;
+//! # Ok(())
+//! # }
+//! ```
+use bytes::Bytes;
+use futures_util::future::{poll_fn, select, Either, FutureExt, LocalBoxFuture};
+use futures_util::ready;
+use futures_util::sink::Sink;
+use futures_util::stream::Stream;
+use log::debug;
+use std::cell::{RefCell, RefMut};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use url::Url;
+
+use crate::time::{timeout, Instant};
+use heartbeat::HeartbeatState;
+
+mod config;
+mod heartbeat;
+mod reconnect;
+
+pub use config::WebSocketConfig;
+pub use heartbeat::Heartbeat;
+pub use reconnect::{ConnectionState, ReconnectPolicy, ReconnectingWebSocket};
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    NativeError(String),
+    StateInit,
+    StateError(String),
+    StateClosed,
+    /// The server refused the handshake with HTTP <status>, only known on
+    /// desktop since browsers hide it
+    Rejected {
+        status: u16,
+    },
+    /// Connecting took longer than a `WebSocketConfig` timeout, or the
+    /// `Heartbeat` went unanswered
+    Timeout,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebSocketMessage {
+    String(String),
+    Binary(Bytes),
+    /// The connection closed with <code>, 1000 for a normal shutdown, and
+    /// <reason>, always the last message received
+    ///
+    /// Sending one closes the connection. Desktop can only close with
+    /// 1000 and no reason, and sending any other fails, as browsers fail
+    /// for codes other than 1000 and 3000 to 4999.
+    Close {
+        code: u16,
+        reason: String,
+    },
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "web-sys"))]
+type WebSocketInner = crate::web_sys::websocket::AsyncWebSocket;
+
+#[cfg(all(target_arch = "wasm32", feature = "stdweb"))]
+type WebSocketInner = crate::std_web::websocket::AsyncWebSocket;
+
+#[cfg(not(target_arch = "wasm32"))]
+type WebSocketInner = crate::desktop::websocket::AsyncWebSocket;
+
+type Sending = LocalBoxFuture<'static, Result<(), WebSocketError>>;
+
+type Receiving = LocalBoxFuture<'static, Result<WebSocketMessage, WebSocketError>>;
+
+/// A websocket connection
+///
+/// As a `Stream`, it ends once the connection is closed. As a `Sink`,
+/// each message is sent before the next is accepted, and closing the
+/// sink closes the connection.
+///
+/// Clones share the connection, and each message is received by only
+/// one of them.
+///
+/// `send` takes the message by reference and shadows `SinkExt::send`,
+/// which is called as `SinkExt::send(&mut socket, msg)` instead.
+pub struct WebSocket {
+    inner: WebSocketInner,
+    heartbeat: Option<Rc<RefCell<HeartbeatState>>>,
+    /// The send or close in progress, for `Sink`
+    sending: Option<Sending>,
+    closing: bool,
+    /// The receive in progress, for `Stream`
+    receiving: Option<Receiving>,
+}
+
+impl Clone for WebSocket {
+    fn clone(&self) -> Self {
+        WebSocket::from_inner(self.inner.clone(), self.heartbeat.clone())
+    }
+}
+
+// TODO: switch to http::Uri
+// TODO: switch to async_trait..
+impl WebSocket {
+    pub async fn connect(url: &Url) -> Result<Self, WebSocketError> {
+        WebSocket::connect_with(url, &WebSocketConfig::default()).await
+    }
+
+    pub async fn connect_with(url: &Url, config: &WebSocketConfig) -> Result<Self, WebSocketError> {
+        let inner = WebSocketInner::connect(url, config).await?;
+        let heartbeat = config
+            .heartbeat
+            .clone()
+            .map(|heartbeat| Rc::new(RefCell::new(HeartbeatState::new(heartbeat))));
+        Ok(WebSocket::from_inner(inner, heartbeat))
+    }
+
+    /// The subprotocol the server picked from `WebSocketConfig::protocol`
+    pub fn protocol(&self) -> Option<String> {
+        self.inner.protocol()
+    }
+
+    /// The round trip time to the server, as of the last answered
+    /// `Heartbeat` ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.heartbeat_state()?.rtt()
+    }
+
+    pub async fn send(&self, msg: &WebSocketMessage) -> Result<(), WebSocketError> {
+        if let Some(state) = self.heartbeat_state() {
+            state.check_alive()?;
+        }
+        self.inner.send(msg).await
+    }
+
+    pub async fn receive(&self) -> Result<WebSocketMessage, WebSocketError> {
+        match self.heartbeat_state() {
+            Some(state) => state.check_alive()?,
+            None => return self.inner.receive().await,
+        }
+
+        let mut receiving = self.inner.receive().boxed_local();
+        loop {
+            let heartbeat = poll_fn(|cx| self.poll_heartbeat(cx));
+            match select(receiving, heartbeat).await {
+                Either::Left((Ok(message), _)) if self.take_pong(&message) => {
+                    receiving = self.inner.receive().boxed_local();
+                }
+                Either::Left((result, _)) => return result,
+                Either::Right((error, _)) => return Err(error),
+            }
+        }
+    }
+
+    pub async fn close(&self) -> Result<(), WebSocketError> {
+        self.inner.close().await
+    }
+
+    /// Separate halves for writing and reading, which can be used
+    /// concurrently from different tasks
+    pub fn split(self) -> (WebSocketWriter, WebSocketReader) {
+        let writer = WebSocketWriter {
+            socket: self.clone(),
+        };
+        (writer, WebSocketReader { socket: self })
+    }
+
+    fn from_inner(inner: WebSocketInner, heartbeat: Option<Rc<RefCell<HeartbeatState>>>) -> Self {
+        WebSocket {
+            inner,
+            heartbeat,
+            sending: None,
+            closing: false,
+            receiving: None,
+        }
+    }
+
+    /// The heartbeat, up to date with the pongs the connection received
+    fn heartbeat_state(&self) -> Option<RefMut<'_, HeartbeatState>> {
+        let mut state = self.heartbeat.as_ref()?.borrow_mut();
+        if let Some((id, at)) = self.inner.last_pong() {
+            state.pong(id, at);
+        }
+        Some(state)
+    }
+
+    /// Send pings as the heartbeat's timer says, only ready once the
+    /// connection is dead
+    fn poll_heartbeat(&self, cx: &mut Context) -> Poll<WebSocketError> {
+        let mut state = match self.heartbeat_state() {
+            Some(state) => state,
+            None => return Poll::Pending,
+        };
+        loop {
+            if let Some(pinging) = &mut state.pinging {
+                // a lost ping is noticed as a missed pong
+                if let Err(e) = ready!(pinging.as_mut().poll(cx)) {
+                    debug!("Failed to ping: {:?}", e);
+                }
+                state.pinging = None;
+            }
+            let id = match ready!(state.poll_ping(cx)) {
+                Ok(id) => id,
+                Err(e) => return Poll::Ready(e),
+            };
+            let inner = self.inner.clone();
+            let message = (state.heartbeat.ping_message)(id);
+            state.pinging = Some(async move { inner.ping(id, &message).await }.boxed_local());
+        }
+    }
+
+    /// Whether <message> answers a browser ping, rather than being for
+    /// the game
+    fn take_pong(&self, message: &WebSocketMessage) -> bool {
+        // desktop pings are frames, answered out of sight
+        if !cfg!(target_arch = "wasm32") {
+            return false;
+        }
+        match self.heartbeat_state() {
+            Some(mut state) => match (state.heartbeat.pong_id)(message) {
+                Some(id) => {
+                    state.pong(id, Instant::now());
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Finish the send or close in progress, if any
+    fn poll_sending(&mut self, cx: &mut Context) -> Poll<Result<(), WebSocketError>> {
+        let result = match &mut self.sending {
+            Some(sending) => ready!(sending.as_mut().poll(cx)),
+            None => return Poll::Ready(Ok(())),
+        };
+        self.sending = None;
+        Poll::Ready(result)
+    }
+}
+
+/// Run <future>, failing with `WebSocketError::Timeout` if it takes
+/// longer than <limit>
+pub(crate) async fn within<T, F>(limit: Option<Duration>, future: F) -> Result<T, WebSocketError>
+where
+    F: std::future::Future<Output = Result<T, WebSocketError>>,
+{
+    match limit {
+        Some(limit) => timeout(limit, future)
+            .await
+            .map_err(|_| WebSocketError::Timeout)?,
+        None => future.await,
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Result<WebSocketMessage, WebSocketError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut receiving = match self.receiving.take() {
+            Some(receiving) => receiving,
+            None => {
+                let socket = self.clone();
+                async move { socket.receive().await }.boxed_local()
+            }
+        };
+        let result = match receiving.as_mut().poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => {
+                self.receiving = Some(receiving);
+                return Poll::Pending;
+            }
+        };
+        match result {
+            Err(WebSocketError::StateClosed) => Poll::Ready(None),
+            result => Poll::Ready(Some(result)),
+        }
+    }
+}
+
+impl Sink<WebSocketMessage> for WebSocket {
+    type Error = WebSocketError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), WebSocketError>> {
+        self.poll_sending(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, msg: WebSocketMessage) -> Result<(), WebSocketError> {
+        if self.closing {
+            return Err(WebSocketError::StateClosed);
+        }
+        let socket = self.clone();
+        self.sending = Some(async move { socket.send(&msg).await }.boxed_local());
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), WebSocketError>> {
+        self.poll_sending(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), WebSocketError>> {
+        if !self.closing {
+            ready!(self.poll_sending(cx))?;
+            let inner = self.inner.clone();
+            let close = async move {
+                match inner.close().await {
+                    // already closed by the server, as when a `forward` ends
+                    Err(WebSocketError::StateClosed) => Ok(()),
+                    result => result,
+                }
+            };
+            self.sending = Some(close.boxed_local());
+            self.closing = true;
+        }
+        self.poll_sending(cx)
+    }
+}
+
+/// The reading half of a `WebSocket`, see `WebSocket::split`
+pub struct WebSocketReader {
+    socket: WebSocket,
+}
+
+impl WebSocketReader {
+    pub async fn receive(&self) -> Result<WebSocketMessage, WebSocketError> {
+        self.socket.receive().await
+    }
+}
+
+impl Stream for WebSocketReader {
+    type Item = Result<WebSocketMessage, WebSocketError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.socket).poll_next(cx)
+    }
+}
+
+/// The writing half of a `WebSocket`, see `WebSocket::split`
+pub struct WebSocketWriter {
+    socket: WebSocket,
+}
+
+impl WebSocketWriter {
+    pub async fn send(&self, msg: &WebSocketMessage) -> Result<(), WebSocketError> {
+        self.socket.send(msg).await
+    }
+
+    pub async fn close(&self) -> Result<(), WebSocketError> {
+        self.socket.close().await
+    }
+}
+
+impl Sink<WebSocketMessage> for WebSocketWriter {
+    type Error = WebSocketError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), WebSocketError>> {
+        Pin::new(&mut self.socket).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, msg: WebSocketMessage) -> Result<(), WebSocketError> {
+        Pin::new(&mut self.socket).start_send(msg)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), WebSocketError>> {
+        Pin::new(&mut self.socket).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), WebSocketError>> {
+        Pin::new(&mut self.socket).poll_close(cx)
+    }
+}
@@ -0,0 +1,368 @@
+use futures_util::future::LocalBoxFuture;
+use log::{debug, warn};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+use url::Url;
+
+use super::{WebSocket, WebSocketConfig, WebSocketError, WebSocketMessage};
+use crate::backoff::{Backoff, Rng};
+use crate::event_bus::{EventBus, Subscriber};
+use crate::time::sleep;
+
+/// When and how often `ReconnectingWebSocket` reconnects, and how much
+/// it buffers meanwhile
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// Failed attempts in a row before giving up, `None` never gives up
+    pub max_attempts: Option<u32>,
+    /// The delay before the first reconnect
+    pub initial_backoff: Duration,
+    /// The longest delay between attempts
+    pub max_backoff: Duration,
+    /// How much longer each delay is than the one before
+    pub multiplier: f64,
+    /// The fraction of each delay that is random, in `[0, 1]`, so that
+    /// clients dropped together don't all reconnect together
+    pub jitter: f64,
+    /// How many messages to buffer while disconnected, sending more
+    /// fails
+    pub max_queued: usize,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: None,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.,
+            jitter: 0.5,
+            max_queued: 256,
+        }
+    }
+}
+
+/// Where a `ReconnectingWebSocket` is in its life, published on its
+/// `EventBus` whenever it changes
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Open,
+    /// The connection dropped or an attempt failed, the next attempt
+    /// starts after <delay>
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+        error: String,
+    },
+    /// For good, either closed by the client or out of attempts
+    Closed {
+        reason: String,
+    },
+}
+
+type Hook = dyn Fn(WebSocket) -> LocalBoxFuture<'static, Result<(), WebSocketError>>;
+
+struct Inner {
+    /// The current connection, numbered to tell it from earlier ones
+    socket: Option<(u64, WebSocket)>,
+    connections: u64,
+    /// Why the last connection dropped, until reconnecting starts
+    dropped: Option<WebSocketError>,
+    queue: VecDeque<WebSocketMessage>,
+    closed: bool,
+    hooks: Vec<Rc<Hook>>,
+}
+
+/// A `WebSocket` that reconnects with backoff whenever it drops
+///
+/// Messages sent while disconnected are queued and sent, in order, once
+/// connected again. Every change of `ConnectionState` is published on an
+/// `EventBus`, and hooks added with `on_connect` run after every
+/// connection, before the queue is sent, to restore subscriptions.
+///
+/// Reconnecting happens inside `receive`, so keep a task receiving.
+/// Clones share the same connection.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use quicksilver_utils_async::websocket::*;
+/// # use futures_util::future::FutureExt;
+/// # use url::Url;
+/// # async fn example() -> Result<(), WebSocketError> {
+/// let url = Url::parse("wss://example.com/lobby").unwrap();
+/// let socket = ReconnectingWebSocket::new(url, ReconnectPolicy::default());
+/// let states = socket.states();
+/// socket.on_connect(|socket| {
+///     async move {
+///         let subscribe = WebSocketMessage::String("subscribe lobby".to_string());
+///         socket.send(&subscribe).await
+///     }
+///     .boxed_local()
+/// });
+///
+/// socket.connect().await?;
+/// loop {
+///     let message = socket.receive().await?;
+///     println!("{:?}", message);
+///     for state in states.drain() {
+///         println!("now {:?}", state);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ReconnectingWebSocket {
+    url: Url,
+    config: Rc<WebSocketConfig>,
+    policy: Rc<ReconnectPolicy>,
+    inner: Rc<RefCell<Inner>>,
+    bus: EventBus,
+    rng: Rc<Rng>,
+}
+
+impl ReconnectingWebSocket {
+    /// A socket for <url>, not connected until `connect` or `receive`
+    pub fn new(url: Url, policy: ReconnectPolicy) -> Self {
+        ReconnectingWebSocket {
+            url,
+            config: Rc::new(WebSocketConfig::default()),
+            policy: Rc::new(policy),
+            inner: Rc::new(RefCell::new(Inner {
+                socket: None,
+                connections: 0,
+                dropped: None,
+                queue: VecDeque::new(),
+                closed: false,
+                hooks: Vec::new(),
+            })),
+            bus: EventBus::new(),
+            rng: Rc::new(Rng::new()),
+        }
+    }
+
+    /// Connect with <config> every time
+    pub fn with_config(mut self, config: WebSocketConfig) -> Self {
+        self.config = Rc::new(config);
+        self
+    }
+
+    /// Publish state changes on <bus>, such as a `TaskContext`'s, instead
+    /// of a private one
+    pub fn with_event_bus(mut self, bus: EventBus) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// Receive every state change from now on
+    pub fn states(&self) -> Subscriber<ConnectionState> {
+        self.bus.subscribe()
+    }
+
+    /// Run <hook> with the new connection after every connection,
+    /// including the first
+    ///
+    /// A failing hook drops the connection and it is attempted again.
+    pub fn on_connect<F>(&self, hook: F)
+    where
+        F: Fn(WebSocket) -> LocalBoxFuture<'static, Result<(), WebSocketError>> + 'static,
+    {
+        self.inner.borrow_mut().hooks.push(Rc::new(hook));
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.inner.borrow().socket.is_some()
+    }
+
+    /// The current connection's round trip time, see `WebSocket::rtt`
+    pub fn rtt(&self) -> Option<Duration> {
+        let inner = self.inner.borrow();
+        inner.socket.as_ref()?.1.rtt()
+    }
+
+    /// Connect if not already connected, retrying as the policy allows
+    pub async fn connect(&self) -> Result<(), WebSocketError> {
+        // after a drop, wait as after a first failed attempt
+        let dropped = self.inner.borrow_mut().dropped.take();
+        let attempt = if dropped.is_some() { 1 } else { 0 };
+        self.connect_after(attempt, dropped).await
+    }
+
+    /// Send <msg>, or queue it until connected again
+    pub async fn send(&self, msg: &WebSocketMessage) -> Result<(), WebSocketError> {
+        let socket = {
+            let inner = self.inner.borrow();
+            if inner.closed {
+                return Err(WebSocketError::StateClosed);
+            }
+            inner.socket.clone()
+        };
+        if let Some((connection, socket)) = socket {
+            match socket.send(msg).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    debug!("Send failed, queueing until reconnected: {:?}", e);
+                    self.disconnected(connection, e);
+                }
+            }
+        }
+        self.enqueue(msg.clone())
+    }
+
+    /// Receive the next message, reconnecting first if needed
+    pub async fn receive(&self) -> Result<WebSocketMessage, WebSocketError> {
+        loop {
+            let socket = self.inner.borrow().socket.clone();
+            let (connection, socket) = match socket {
+                Some(socket) => socket,
+                None => {
+                    self.connect().await?;
+                    continue;
+                }
+            };
+            match socket.receive().await {
+                Ok(message) => return Ok(message),
+                Err(_) if self.inner.borrow().closed => return Err(WebSocketError::StateClosed),
+                Err(e) => self.disconnected(connection, e),
+            }
+        }
+    }
+
+    /// Close the connection for good, dropping queued messages
+    pub async fn close(&self) -> Result<(), WebSocketError> {
+        let socket = self.inner.borrow_mut().socket.take();
+        self.shut_down("Closed by the client".to_string());
+        match socket {
+            Some((_, socket)) => socket.close().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Connect, with <attempt> attempts failed so far, the last with
+    /// <error>
+    async fn connect_after(
+        &self,
+        mut attempt: u32,
+        mut error: Option<WebSocketError>,
+    ) -> Result<(), WebSocketError> {
+        loop {
+            if self.inner.borrow().closed {
+                return Err(WebSocketError::StateClosed);
+            }
+            if self.inner.borrow().socket.is_some() {
+                return Ok(());
+            }
+            if let Some(error) = &error {
+                self.wait_to_reconnect(attempt, error).await;
+            }
+
+            let e = match self.try_connect().await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            attempt += 1;
+            if self
+                .policy
+                .max_attempts
+                .is_some_and(|max_attempts| attempt >= max_attempts)
+            {
+                self.shut_down(format!("Gave up after {} attempts: {:?}", attempt, e));
+                return Err(e);
+            }
+            error = Some(e);
+        }
+    }
+
+    async fn try_connect(&self) -> Result<(), WebSocketError> {
+        self.publish(ConnectionState::Connecting);
+        let socket = WebSocket::connect_with(&self.url, &self.config).await?;
+
+        let hooks = self.inner.borrow().hooks.clone();
+        for hook in hooks {
+            hook(socket.clone()).await?;
+        }
+        loop {
+            let next = self.inner.borrow_mut().queue.pop_front();
+            let msg = match next {
+                Some(msg) => msg,
+                None => break,
+            };
+            if let Err(e) = socket.send(&msg).await {
+                self.inner.borrow_mut().queue.push_front(msg);
+                return Err(e);
+            }
+        }
+
+        {
+            let inner: &mut Inner = &mut self.inner.borrow_mut();
+            if !inner.closed {
+                inner.connections += 1;
+                inner.socket = Some((inner.connections, socket.clone()));
+            }
+        }
+        if self.inner.borrow().closed {
+            let _ = socket.close().await;
+            return Err(WebSocketError::StateClosed);
+        }
+        self.publish(ConnectionState::Open);
+        Ok(())
+    }
+
+    async fn wait_to_reconnect(&self, attempt: u32, error: &WebSocketError) {
+        let backoff = Backoff {
+            initial: self.policy.initial_backoff,
+            max: self.policy.max_backoff,
+            multiplier: self.policy.multiplier,
+            jitter: self.policy.jitter,
+        };
+        let delay = backoff.delay(attempt - 1, self.rng.next_f64());
+        debug!("Reconnecting to {} in {:?}", self.url, delay);
+        self.publish(ConnectionState::Reconnecting {
+            attempt,
+            delay,
+            error: format!("{:?}", error),
+        });
+        sleep(delay).await;
+    }
+
+    /// Forget connection number <connection>, which failed with <error>,
+    /// if it is still the current one
+    fn disconnected(&self, connection: u64, error: WebSocketError) {
+        let inner: &mut Inner = &mut self.inner.borrow_mut();
+        if matches!(inner.socket, Some((current, _)) if current == connection) {
+            inner.socket = None;
+            inner.dropped = Some(error);
+        }
+    }
+
+    fn enqueue(&self, msg: WebSocketMessage) -> Result<(), WebSocketError> {
+        let inner: &mut Inner = &mut self.inner.borrow_mut();
+        if inner.queue.len() >= self.policy.max_queued {
+            return Err(WebSocketError::StateError("Send queue is full".to_string()));
+        }
+        inner.queue.push_back(msg);
+        Ok(())
+    }
+
+    fn shut_down(&self, reason: String) {
+        {
+            let inner: &mut Inner = &mut self.inner.borrow_mut();
+            if inner.closed {
+                return;
+            }
+            inner.closed = true;
+            inner.socket = None;
+            inner.queue.clear();
+        }
+        self.publish(ConnectionState::Closed { reason });
+    }
+
+    fn publish(&self, state: ConnectionState) {
+        if self.bus.publish(state).is_err() {
+            warn!("Connection state events are full, dropping one");
+        }
+    }
+}
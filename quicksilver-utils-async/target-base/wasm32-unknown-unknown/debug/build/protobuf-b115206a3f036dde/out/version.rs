@@ -0,0 +1,7 @@
+/// protobuf crate version
+pub const VERSION: &'static str = "2.14.0";
+/// This symbol is used by codegen
+#[doc(hidden)]
+pub const VERSION_IDENT: &'static str = "VERSION_2_14_0";
+/// This symbol can be referenced to assert that proper version of crate is used
+pub const VERSION_2_14_0: () = ();
@@ -0,0 +1,169 @@
+use async_std::task::block_on;
+use futures_util::stream::StreamExt;
+use http::Method;
+use quicksilver_utils_async::request::*;
+use quicksilver_utils_async::time::{set_clock, ManualClock};
+use std::time::Duration;
+
+mod common;
+use common::{get, request_count};
+
+/// Answers a GET of <uri> with <body>, fresh for a minute
+fn cacheable(server: &MockServiceClient, uri: &str, body: &'static str) {
+    server.transport().on(Method::GET, uri, move |_| {
+        Ok(http::Response::builder()
+            .header("cache-control", "max-age=60")
+            .body(body.into())
+            .unwrap())
+    });
+}
+
+#[test]
+fn stale_responses_are_revalidated() {
+    let clock = ManualClock::new();
+    let _guard = set_clock(clock.clone());
+
+    let server = MockServiceClient::new();
+    server
+        .transport()
+        .on(Method::GET, "http://localhost/manifest.json", |request| {
+            let unchanged = request
+                .headers()
+                .get("if-none-match")
+                .is_some_and(|etag| etag == "\"v1\"");
+            Ok(http::Response::builder()
+                .status(if unchanged { 304 } else { 200 })
+                .header(
+                    "cache-control",
+                    if unchanged {
+                        "max-age=600"
+                    } else {
+                        "max-age=60"
+                    },
+                )
+                .header("etag", "\"v1\"")
+                .body(if unchanged { "" } else { "{}" }.into())
+                .unwrap())
+        });
+
+    let client = CachingClient::wrap(server);
+    let uri = "http://localhost/manifest.json";
+    let first = get(&client, uri);
+    let second = get(&client, uri);
+    assert_eq!(second.body(), first.body());
+    assert_eq!(request_count(client.inner()), 1);
+
+    // stale after a minute, so revalidated
+    clock.advance(Duration::from_secs(61));
+    let third = get(&client, uri);
+    assert_eq!(third.status(), 200);
+    assert_eq!(&third.body()[..], b"{}");
+    assert_eq!(request_count(client.inner()), 2);
+
+    // the 304 made it fresh for ten minutes
+    clock.advance(Duration::from_secs(300));
+    let fourth = get(&client, uri);
+    assert_eq!(fourth.headers()["cache-control"], "max-age=600");
+    assert_eq!(request_count(client.inner()), 2);
+}
+
+#[test]
+fn responses_vary_by_the_headers_they_name() {
+    let server = MockServiceClient::new();
+    server
+        .transport()
+        .on(Method::GET, "http://localhost/greeting", |request| {
+            let english = request
+                .headers()
+                .get("accept-language")
+                .map_or(true, |lang| lang == "en");
+            Ok(http::Response::builder()
+                .header("cache-control", "max-age=60")
+                .header("vary", "accept-language")
+                .body(if english { "hello" } else { "bonjour" }.into())
+                .unwrap())
+        });
+
+    let client = CachingClient::wrap(server);
+    let greet = |lang: &str| {
+        let request = client.get("http://localhost/greeting".parse().unwrap());
+        block_on(request.header("accept-language", lang).send()).unwrap()
+    };
+    assert_eq!(&greet("en").body()[..], b"hello");
+    assert_eq!(&greet("fr").body()[..], b"bonjour");
+    assert_eq!(&greet("fr").body()[..], b"bonjour");
+    assert_eq!(request_count(client.inner()), 2);
+}
+
+#[test]
+fn authorized_responses_are_not_shared_unless_public() {
+    let server = MockServiceClient::new();
+    cacheable(&server, "http://localhost/inbox", "no new messages");
+
+    let client = CachingClient::wrap(server);
+    for _ in 0..2 {
+        let request = client.get("http://localhost/inbox".parse().unwrap());
+        block_on(request.header("authorization", "Bearer monk").send()).unwrap();
+    }
+    assert_eq!(request_count(client.inner()), 2);
+}
+
+#[test]
+fn storage_keeps_responses_with_an_etag_across_runs() {
+    let dir = std::env::temp_dir().join(format!("cache-test-{}", std::process::id()));
+    let server = || {
+        let server = MockServiceClient::new();
+        server
+            .transport()
+            .on(Method::GET, "http://localhost/manifest.json", |_| {
+                Ok(http::Response::builder()
+                    .header("cache-control", "max-age=60")
+                    .header("etag", "\"v1\"")
+                    .body("{}".into())
+                    .unwrap())
+            });
+        cacheable(&server, "http://localhost/motd", "{}");
+        server
+    };
+
+    let first_run = CachingClient::wrap(server()).with_storage(DirectoryStorage::new(&dir));
+    get(&first_run, "http://localhost/manifest.json");
+    get(&first_run, "http://localhost/motd");
+
+    // still fresh, and only the one that could be revalidated was kept
+    let second_run = CachingClient::wrap(server()).with_storage(DirectoryStorage::new(&dir));
+    get(&second_run, "http://localhost/manifest.json");
+    get(&second_run, "http://localhost/motd");
+    let requests = second_run.inner().transport().requests();
+    let paths: Vec<&str> = requests
+        .iter()
+        .map(|request| request.uri().path())
+        .collect();
+    assert_eq!(paths, vec!["/motd"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn streamed_responses_are_cached_once_read_to_the_end() {
+    let server = MockServiceClient::new();
+    server
+        .transport()
+        .on(Method::GET, "http://localhost/level.bin", |_| {
+            Ok(http::Response::builder()
+                .header("cache-control", "max-age=60")
+                .body(vec![0u8; 1024].into())
+                .unwrap())
+        });
+    let client = CachingClient::wrap(server);
+    let uri: http::Uri = "http://localhost/level.bin".parse().unwrap();
+
+    // dropped before the end, so not cached
+    drop(block_on(client.get(uri.clone()).send_streaming()).unwrap());
+
+    let response = block_on(client.get(uri.clone()).send_streaming()).unwrap();
+    let chunks: Vec<_> = block_on(response.into_body().collect());
+    assert_eq!(chunks.len(), 1);
+
+    get(&client, "http://localhost/level.bin");
+    assert_eq!(request_count(client.inner()), 2);
+}
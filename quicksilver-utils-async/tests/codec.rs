@@ -0,0 +1,52 @@
+use bytes::Bytes;
+use quicksilver_utils_async::codec::{Codec, Decode, Encode};
+use quicksilver_utils_async::request::{RequestError, Result};
+
+/// Plain utf8 text bodies
+struct Text;
+
+impl Codec for Text {
+    fn content_type() -> &'static str {
+        "text/plain; charset=utf-8"
+    }
+}
+
+impl Encode<String> for Text {
+    fn encode(value: &String) -> Result<Bytes> {
+        Ok(Bytes::from(value.clone()))
+    }
+}
+
+impl Decode<String> for Text {
+    fn decode(body: &[u8]) -> Result<String> {
+        String::from_utf8(body.to_vec())
+            .map_err(|e| RequestError::Decode(format!("Invalid utf8 {}", e)))
+    }
+}
+
+#[test]
+fn custom_codecs_round_trip() {
+    let body = Text::encode(&"hello".to_string()).unwrap();
+    assert_eq!(Text::decode(&body).unwrap(), "hello");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_round_trips() {
+    use quicksilver_utils_async::codec::Json;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct HighScore {
+        name: String,
+        score: u32,
+    }
+
+    let high_score = HighScore {
+        name: "monk".to_string(),
+        score: 9000,
+    };
+    let body = Json::encode(&high_score).unwrap();
+    assert_eq!(&body[..], &br#"{"name":"monk","score":9000}"#[..]);
+    let decoded: HighScore = Json::decode(&body).unwrap();
+    assert_eq!(decoded, high_score);
+}
@@ -0,0 +1,91 @@
+//! Local servers and mock clients for the integration tests
+#![allow(dead_code)]
+
+use async_std::io::prelude::*;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task::block_on;
+use quicksilver_utils_async::request::{MockServiceClient, Response, ServiceClient};
+use url::Url;
+
+/// GET <uri> with <client>, failing the test if the request fails
+pub fn get<C: ServiceClient>(client: &C, uri: &str) -> Response {
+    block_on(client.get(uri.parse().unwrap()).send()).unwrap()
+}
+
+/// The number of requests <client>'s mock transport has seen
+pub fn request_count(client: &MockServiceClient) -> usize {
+    client.transport().requests().len()
+}
+
+/// A listener on a free local port, and its url with <scheme>
+pub async fn listen(scheme: &str) -> (TcpListener, Url) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("{}://{}/", scheme, listener.local_addr().unwrap());
+    (listener, Url::parse(&url).unwrap())
+}
+
+/// The request head the next connection sends, up to the blank line
+pub async fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+/// The response accepting the websocket handshake <head>, with <extra>
+/// header lines
+pub fn websocket_response(head: &str, extra: &str) -> String {
+    let key = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some(value.trim()).filter(|_| name.eq_ignore_ascii_case("sec-websocket-key"))
+        })
+        .unwrap();
+    let mut digest = sha1::Sha1::new();
+    digest.update(key.as_bytes());
+    digest.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+    format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n{}\r\n",
+        base64::encode(&digest.digest().bytes()),
+        extra
+    )
+}
+
+/// Accept a websocket connection, shaking hands with <extra> response
+/// header lines
+pub async fn accept_websocket(listener: &TcpListener, extra: &str) -> TcpStream {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let head = read_head(&mut stream).await;
+    let response = websocket_response(&head, extra);
+    stream.write_all(response.as_bytes()).await.unwrap();
+    stream
+}
+
+/// An unmasked frame from the server, small enough for a one byte length
+pub fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    assert!(payload.len() < 126);
+    let mut frame = vec![0x80 | opcode, payload.len() as u8];
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The opcode and unmasked payload of the next frame from the client
+pub async fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header).await.unwrap();
+    let length = (header[1] & 0x7f) as usize;
+    assert!(length < 126);
+    let mut mask = [0; 4];
+    stream.read_exact(&mut mask).await.unwrap();
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload).await.unwrap();
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    (header[0] & 0x0f, payload)
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "specs")]
+
+use async_std::task::block_on;
+use quicksilver_utils_async::ecs::*;
+use quicksilver_utils_async::task_context::TaskContext;
+use specs_ecs::prelude::*;
+
+#[derive(Clone, Debug, PartialEq)]
+struct ServerMessage(String);
+
+struct SpawnGreeting;
+
+impl<'a> System<'a> for SpawnGreeting {
+    type SystemData = WriteExpect<'a, TaskContextResource>;
+
+    fn run(&mut self, mut tasks: Self::SystemData) {
+        let task_context = tasks.task_context.clone();
+        tasks.task_context.spawn(async move {
+            task_context
+                .publish(ServerMessage("hello".to_string()))
+                .unwrap();
+        });
+    }
+}
+
+#[test]
+fn events_published_by_tasks_reach_the_world() {
+    let mut world = World::new();
+    let task_context: TaskContext<'static> = TaskContext::new();
+    insert_task_context(&mut world, task_context.clone());
+    world.insert(TaskEvents::<ServerMessage>::default());
+    let mut drain_system = DrainTaskEvents::<ServerMessage>::new(&task_context);
+
+    SpawnGreeting.run_now(&world);
+    block_on(run_until_stalled::<()>(&world));
+    drain_system.run_now(&world);
+
+    let messages = world.read_resource::<TaskEvents<ServerMessage>>();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(
+        messages.iter().next(),
+        Some(&ServerMessage("hello".to_string()))
+    );
+}
@@ -0,0 +1,77 @@
+use async_std::task::block_on;
+use quicksilver_utils_async::event_bus::{EventBus, OverflowPolicy};
+use quicksilver_utils_async::task_context::TaskContext;
+
+#[derive(Clone, Debug, PartialEq)]
+struct ChatMessage(String);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Ticked;
+
+#[test]
+fn every_subscriber_gets_each_event_up_to_its_bound() {
+    let bus = EventBus::new();
+    bus.set_bounds::<Ticked>(2, OverflowPolicy::DropOldest);
+
+    let chat_log = bus.subscribe::<ChatMessage>();
+    let chat_bubbles = bus.subscribe::<ChatMessage>();
+    let ticks = bus.subscribe::<Ticked>();
+
+    bus.publish(ChatMessage("hello".to_string())).unwrap();
+    for _ in 0..5 {
+        bus.publish(Ticked).unwrap();
+    }
+
+    assert_eq!(chat_log.drain(), vec![ChatMessage("hello".to_string())]);
+    assert_eq!(chat_bubbles.drain_up_to(10).len(), 1);
+    assert_eq!(ticks.drain(), vec![Ticked, Ticked]);
+}
+
+/// A task context whose subscribers each hold one `u32` before `send`
+/// waits
+fn backpressured_scores() -> TaskContext<'static> {
+    let task_context: TaskContext = TaskContext::new();
+    task_context
+        .bus()
+        .set_bounds::<u32>(1, OverflowPolicy::Backpressure);
+    task_context
+}
+
+/// Spawn a task sending 0, 1 and 2
+fn send_scores(task_context: &mut TaskContext<'static>) {
+    let bus = task_context.bus().clone();
+    task_context.spawn(async move {
+        for score in 0..3u32 {
+            bus.send(score).await
+        }
+    });
+}
+
+#[test]
+fn send_waits_for_room() {
+    let mut task_context = backpressured_scores();
+    let scores = task_context.subscribe::<u32>();
+    send_scores(&mut task_context);
+
+    block_on(task_context.run_until_stalled());
+    assert_eq!(scores.drain(), vec![0]);
+    block_on(task_context.run_until_stalled());
+    assert_eq!(scores.drain(), vec![1]);
+}
+
+#[test]
+fn dropping_a_subscriber_releases_waiting_sends() {
+    let mut task_context = backpressured_scores();
+    let slow = task_context.subscribe::<u32>();
+    let fast = task_context.subscribe::<u32>();
+    send_scores(&mut task_context);
+
+    block_on(task_context.run_until_stalled());
+    assert_eq!(fast.drain(), vec![0]);
+    block_on(task_context.run_until_stalled());
+    assert!(fast.is_empty());
+
+    drop(slow);
+    block_on(task_context.run_until_stalled());
+    assert_eq!(fast.drain(), vec![1]);
+}
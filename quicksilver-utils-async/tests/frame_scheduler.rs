@@ -0,0 +1,18 @@
+use async_std::task::block_on;
+use quicksilver_utils_async::frame_scheduler::FrameScheduler;
+use quicksilver_utils_async::time::{set_clock, ManualClock};
+use std::time::Duration;
+
+#[test]
+fn frames_catch_up_on_elapsed_updates() {
+    let clock = ManualClock::new();
+    let _guard = set_clock(clock.clone());
+    let mut scheduler = FrameScheduler::new(100);
+
+    assert_eq!(block_on(scheduler.next_frame()).updates(), 0);
+
+    clock.advance(Duration::from_millis(25));
+    let frame = block_on(scheduler.next_frame());
+    assert_eq!(frame.updates(), 2);
+    assert!((frame.alpha() - 0.5).abs() < 0.001);
+}
@@ -0,0 +1,60 @@
+#![cfg(feature = "protobuf")]
+
+use async_std::task::block_on;
+use bytes::BytesMut;
+use futures_util::stream::StreamExt;
+use http::Method;
+use protobuf::well_known_types::{StringValue, UInt32Value};
+use quicksilver_utils_async::request::grpc_web::*;
+use quicksilver_utils_async::request::*;
+
+const COUNTDOWN: &str = "http://localhost/demo.Rocket/Countdown";
+
+/// A client whose server counts down from the requested number
+fn countdown_client() -> GrpcWebClient<MockServiceClient> {
+    let server = MockServiceClient::new();
+    server.transport().on(Method::POST, COUNTDOWN, |request| {
+        let mut body = BytesMut::new();
+        let from: UInt32Value = protobuf::parse_from_bytes(&request.body()[5..]).unwrap();
+        for count in (1..=from.get_value()).rev() {
+            let mut message = StringValue::new();
+            message.set_value(count.to_string());
+            body.extend_from_slice(&message_frame(&message).unwrap());
+        }
+        body.extend_from_slice(&trailers_frame(0, ""));
+        Ok(http::Response::builder()
+            .header("content-type", GRPC_WEB_PROTO)
+            .body(body.freeze())
+            .unwrap())
+    });
+    GrpcWebClient::wrap(server)
+}
+
+fn from(value: u32) -> UInt32Value {
+    let mut from = UInt32Value::new();
+    from.set_value(value);
+    from
+}
+
+#[test]
+fn server_streaming_calls_yield_every_message() {
+    let client = countdown_client();
+    let countdown: Vec<String> = block_on(async {
+        let messages = client
+            .server_streaming::<_, StringValue>(COUNTDOWN.parse().unwrap(), &from(3))
+            .await
+            .unwrap();
+        messages
+            .map(|message| message.unwrap().take_value())
+            .collect()
+            .await
+    });
+    assert_eq!(countdown, vec!["3", "2", "1"]);
+}
+
+#[test]
+fn unary_calls_need_exactly_one_message() {
+    let client = countdown_client();
+    let failed = block_on(client.unary::<_, StringValue>(COUNTDOWN.parse().unwrap(), &from(3)));
+    assert!(matches!(failed, Err(RequestError::Decode(_))));
+}
@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+use quicksilver_utils_async::request::middleware::*;
+use quicksilver_utils_async::request::*;
+use std::cell::Cell;
+use std::rc::Rc;
+
+mod common;
+use common::get;
+
+/// A server that only accepts <token>
+fn server(token: &'static str) -> Client<LoopbackTransport> {
+    Client::with_transport(LoopbackTransport::new(move |request| {
+        let authorized = request.headers()["authorization"] == format!("Bearer {}", token);
+        let status = if authorized { 200 } else { 401 };
+        Ok(http::Response::builder()
+            .status(status)
+            .body(Default::default())
+            .unwrap())
+    }))
+}
+
+/// Hands out numbered tokens
+struct Tokens {
+    issued: Rc<Cell<u32>>,
+}
+
+#[async_trait(?Send)]
+impl TokenProvider for Tokens {
+    async fn token(&self) -> Result<String> {
+        Ok(format!("token-{}", self.issued.get()))
+    }
+
+    async fn refresh(&self) -> Result<String> {
+        self.issued.set(self.issued.get() + 1);
+        self.token().await
+    }
+}
+
+#[test]
+fn rejected_tokens_are_refreshed_once() {
+    let issued = Rc::new(Cell::new(0));
+    let metrics = LatencyMetrics::new();
+    let client = MiddlewareClient::wrap(server("token-1"))
+        .with(Logging::new())
+        .with(metrics.clone())
+        .with(BearerAuth::new(Tokens {
+            issued: issued.clone(),
+        }));
+
+    assert_eq!(get(&client, "http://localhost/me").status(), 200);
+    assert_eq!(issued.get(), 1);
+    assert_eq!(metrics.stats().request_count(), 1);
+}
+
+#[test]
+#[allow(deprecated)]
+fn set_auth_token_sends_a_bearer_token() {
+    let mut client = MiddlewareClient::wrap(server("secret"));
+    client.set_auth_token("secret");
+
+    assert_eq!(get(&client, "http://localhost/me").status(), 200);
+}
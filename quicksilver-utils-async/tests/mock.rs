@@ -0,0 +1,76 @@
+use async_std::task::block_on;
+use http::Method;
+use quicksilver_utils_async::request::*;
+
+mod common;
+use common::get;
+
+#[test]
+fn mocks_answer_by_route_and_record_requests() {
+    let client = MockServiceClient::new();
+    let mock = client.transport();
+    mock.respond(Method::PUT, "http://localhost/scores/monk", 204, "");
+    mock.on(Method::GET, "http://localhost/scores/monk", |_request| {
+        Err(RequestError::Transport("connection reset".to_string()))
+    });
+
+    let put = client
+        .put("http://localhost/scores/monk".parse().unwrap())
+        .body("9000");
+    assert_eq!(block_on(put.send()).unwrap().status(), 204);
+
+    let failed = block_on(
+        client
+            .get("http://localhost/scores/monk".parse().unwrap())
+            .send(),
+    );
+    assert!(matches!(failed, Err(RequestError::Transport(_))));
+
+    let missing = get(&client, "http://localhost/scores/ghost");
+    assert_eq!(missing.status(), 404);
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(&requests[0].body()[..], b"9000");
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn proto_mocks_decode_and_encode_calls() {
+    use protobuf::well_known_types::StringValue;
+
+    let client = MockServiceClient::new();
+    client
+        .transport()
+        .on_proto("http://localhost/shout", |request: StringValue| {
+            let mut response = StringValue::new();
+            response.set_value(request.get_value().to_uppercase());
+            Ok(response)
+        });
+
+    let mut request = StringValue::new();
+    request.set_value("hello".to_string());
+    let response: StringValue =
+        block_on(client.post_proto("http://localhost/shout".parse().unwrap(), &request)).unwrap();
+    assert_eq!(response.get_value(), "HELLO");
+}
+
+#[test]
+fn loopback_echoes_by_default() {
+    let echo: Client<LoopbackTransport> = Client::new();
+    let put = echo
+        .put("http://localhost/echo".parse().unwrap())
+        .body("hello");
+    assert_eq!(&block_on(put.send()).unwrap().body()[..], b"hello");
+}
+
+#[test]
+fn loopback_answers_with_its_handler() {
+    let teapot = Client::with_transport(LoopbackTransport::new(|_request| {
+        Ok(http::Response::builder()
+            .status(418)
+            .body("short and stout".into())
+            .unwrap())
+    }));
+    assert_eq!(get(&teapot, "http://localhost/coffee").status(), 418);
+}
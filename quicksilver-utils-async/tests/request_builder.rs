@@ -0,0 +1,131 @@
+use async_std::task::block_on;
+use futures_util::stream::StreamExt;
+use http::Method;
+use quicksilver_utils_async::request::*;
+use quicksilver_utils_async::task_context::TaskContext;
+
+fn client() -> Client<LoopbackTransport> {
+    Client::with_transport(LoopbackTransport::new(|_request| {
+        Ok(http::Response::new(Default::default()))
+    }))
+}
+
+#[test]
+fn query_appends_to_the_existing_query() {
+    let request = client()
+        .get("http://localhost/scores?page=2".parse().unwrap())
+        .query(&[("player", "ada lovelace")])
+        .build()
+        .unwrap();
+    assert_eq!(
+        request.uri().to_string(),
+        "http://localhost/scores?page=2&player=ada+lovelace"
+    );
+}
+
+#[test]
+fn empty_query_leaves_the_uri_alone() {
+    let no_pairs: &[(&str, &str)] = &[];
+    let request = client()
+        .get("http://localhost/scores".parse().unwrap())
+        .query(no_pairs)
+        .build()
+        .unwrap();
+    assert_eq!(request.uri().to_string(), "http://localhost/scores");
+}
+
+#[test]
+fn form_replaces_the_content_type() {
+    let request = client()
+        .request(Method::POST, "http://localhost/login".parse().unwrap())
+        .header("content-type", "text/plain")
+        .form(&[("user", "monk"), ("greeting", "hi & bye")])
+        .build()
+        .unwrap();
+    let content_types: Vec<_> = request.headers().get_all("content-type").iter().collect();
+    assert_eq!(content_types, vec!["application/x-www-form-urlencoded"]);
+    assert_eq!(&request.body()[..], b"user=monk&greeting=hi+%26+bye");
+}
+
+#[test]
+fn multipart_sends_fields_and_files() {
+    let server = MockServiceClient::new();
+    server
+        .transport()
+        .respond(Method::POST, "http://localhost/replays", 201, "");
+
+    let form = Multipart::new().text("level", "1-1").file(
+        "replay",
+        "run.replay",
+        "application/octet-stream",
+        vec![1u8, 2, 3],
+    );
+    let response = block_on(
+        server
+            .request(Method::POST, "http://localhost/replays".parse().unwrap())
+            .multipart(form)
+            .send(),
+    )
+    .unwrap();
+    assert_eq!(response.status(), 201);
+
+    let sent = &server.transport().requests()[0];
+    let content_type = sent.headers()["content-type"].to_str().unwrap();
+    assert!(content_type.starts_with("multipart/form-data; boundary="));
+    let body = String::from_utf8_lossy(sent.body());
+    assert!(body.contains("Content-Disposition: form-data; name=\"level\"\r\n\r\n1-1\r\n"));
+    assert!(body.contains("name=\"replay\"; filename=\"run.replay\""));
+}
+
+#[test]
+fn multipart_refuses_line_breaks_in_headers() {
+    let form = Multipart::new().file("replay", "run.replay", "text/plain\r\nX-Injected: 1", "");
+    let request = client()
+        .request(Method::POST, "http://localhost/replays".parse().unwrap())
+        .multipart(form)
+        .build();
+    assert!(matches!(request, Err(RequestError::Encode(_))));
+}
+
+#[test]
+fn error_for_status_keeps_the_status_and_body() {
+    let response = http::Response::builder()
+        .status(503)
+        .body("try again later".into())
+        .unwrap();
+    match error_for_status(response) {
+        Err(RequestError::Http { status, body }) => {
+            assert_eq!(status, 503);
+            assert_eq!(&body[..], b"try again later");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn downloads_report_progress() {
+    let mut task_context: TaskContext<Option<f32>> = TaskContext::new();
+    let loading_screen = task_context.clone();
+    task_context.spawn(async move {
+        let client = Client::with_transport(LoopbackTransport::new(|_request| {
+            Ok(http::Response::builder()
+                .header("content-length", "5")
+                .body("tiles".into())
+                .unwrap())
+        }));
+        let mut download = client
+            .get("http://localhost/level-1.map".parse().unwrap())
+            .download()
+            .await
+            .unwrap();
+        while let Some(event) = download.next().await {
+            match event.unwrap() {
+                DownloadEvent::Progress(progress) => loading_screen.dispatch(progress.fraction()),
+                DownloadEvent::Finished(response) => assert_eq!(&response.body()[..], b"tiles"),
+            }
+        }
+    });
+
+    block_on(task_context.run_until_stalled());
+    assert_eq!(task_context.drain(), vec![Some(0.), Some(1.)]);
+}
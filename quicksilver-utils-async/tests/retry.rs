@@ -0,0 +1,30 @@
+use quicksilver_utils_async::request::*;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+mod common;
+use common::get;
+
+#[test]
+fn unavailable_requests_are_retried() {
+    // unavailable for the first two requests
+    let calls = Rc::new(Cell::new(0));
+    let counted_calls = calls.clone();
+    let flaky = Client::with_transport(LoopbackTransport::new(move |_request| {
+        counted_calls.set(counted_calls.get() + 1);
+        let status = if counted_calls.get() <= 2 { 503 } else { 200 };
+        Ok(http::Response::builder()
+            .status(status)
+            .body(Default::default())
+            .unwrap())
+    }));
+
+    let policy = RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    };
+    let client = RetryClient::with_policy(flaky, policy);
+    assert_eq!(get(&client, "http://localhost/scores").status(), 200);
+    assert_eq!(calls.get(), 3);
+}
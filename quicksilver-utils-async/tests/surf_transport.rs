@@ -0,0 +1,52 @@
+use async_std::io::prelude::*;
+use async_std::task::{block_on, spawn};
+use http::header::CONTENT_TYPE;
+use http::Method;
+use quicksilver_utils_async::request::*;
+
+mod common;
+use common::{listen, read_head};
+
+/// The header lines a local server receives for the request
+async fn sent(method: Method, content_type: Option<&str>, body: &'static str) -> Vec<String> {
+    let (listener, uri) = listen("http").await;
+    let server = spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let head = read_head(&mut stream).await;
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .await
+            .unwrap();
+        head
+    });
+
+    let client = Client::with_transport(SurfTransport::new());
+    let mut request = client
+        .request(method, uri.as_str().parse().unwrap())
+        .body(body);
+    if let Some(content_type) = content_type {
+        request = request.header(CONTENT_TYPE, content_type);
+    }
+    request.send().await.unwrap();
+    let head = server.await;
+    head.lines().skip(1).map(str::to_lowercase).collect()
+}
+
+#[test]
+fn headers_replace_surfs_defaults() {
+    let head = block_on(sent(Method::POST, Some("application/json"), "{}"));
+    let content_types: Vec<&String> = head
+        .iter()
+        .filter(|line| line.starts_with("content-type:"))
+        .collect();
+    assert_eq!(content_types, vec!["content-type: application/json"]);
+    assert!(head.contains(&"content-length: 2".to_string()));
+}
+
+#[test]
+fn bodiless_get_sends_no_body_headers() {
+    let head = block_on(sent(Method::GET, None, ""));
+    assert!(!head
+        .iter()
+        .any(|line| line.starts_with("content-") || line.starts_with("transfer-encoding:")));
+}
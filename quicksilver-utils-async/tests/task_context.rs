@@ -0,0 +1,80 @@
+use async_std::task::block_on;
+use quicksilver_utils_async::task_context::{yield_now, JoinError, Priority, TaskContext};
+use quicksilver_utils_async::time::sleep_ms;
+
+#[test]
+fn runs_until_woken_tasks_are_waiting_too() {
+    let mut task_context: TaskContext<&str> = TaskContext::new();
+    let mut scores = task_context.subscribe::<u32>();
+    let cloned_task_context = task_context.clone();
+    task_context.spawn_with_priority(Priority::High, async move {
+        scores.recv().await;
+        cloned_task_context.dispatch("high got it")
+    });
+    let cloned_task_context = task_context.clone();
+    task_context.spawn_with_priority(Priority::Low, async move {
+        cloned_task_context.publish(7u32).unwrap();
+        cloned_task_context.dispatch("low published")
+    });
+
+    block_on(task_context.run_until_stalled());
+    assert_eq!(task_context.drain(), vec!["low published", "high got it"]);
+}
+
+#[test]
+fn run_at_most_stops_after_its_polls_high_priority_first() {
+    let mut task_context: TaskContext<&str> = TaskContext::new();
+    for name in &["pathfinding", "asset decoding"] {
+        let cloned_task_context = task_context.clone();
+        task_context.spawn_with_priority(Priority::Low, async move {
+            loop {
+                cloned_task_context.dispatch(name);
+                yield_now().await
+            }
+        });
+    }
+    let cloned_task_context = task_context.clone();
+    task_context.spawn_with_priority(Priority::High, async move {
+        cloned_task_context.dispatch("input")
+    });
+
+    block_on(task_context.run_at_most(2));
+    let events = task_context.drain();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0], "input");
+
+    block_on(task_context.run_at_most(10));
+    assert_eq!(task_context.drain().len(), 10);
+}
+
+#[test]
+fn handles_await_output_or_abort() {
+    let mut task_context: TaskContext<()> = TaskContext::new();
+    let answer = task_context.spawn(async { 42 });
+    let forever = task_context.spawn(async {
+        loop {
+            sleep_ms(1000).await
+        }
+    });
+
+    block_on(task_context.run_until_stalled());
+    assert!(answer.is_finished());
+    assert_eq!(block_on(answer), Ok(42));
+
+    forever.abort();
+    assert_eq!(block_on(forever), Err(JoinError::Aborted));
+}
+
+#[test]
+fn dropping_a_scope_aborts_its_tasks() {
+    let task_context: TaskContext<()> = TaskContext::new();
+    let mut room_scope = task_context.scope();
+    let ambience = room_scope.spawn(async {
+        loop {
+            sleep_ms(1000).await
+        }
+    });
+
+    drop(room_scope);
+    assert!(ambience.is_finished());
+}
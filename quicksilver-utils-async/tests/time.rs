@@ -0,0 +1,56 @@
+use async_std::task::block_on;
+use futures_util::FutureExt;
+use quicksilver_utils_async::task_context::TaskContext;
+use quicksilver_utils_async::time::*;
+use std::time::Duration;
+
+#[test]
+fn manual_clocks_wake_timers_when_advanced() {
+    let clock = ManualClock::new();
+    let _guard = set_clock(clock.clone());
+
+    let mut task_context: TaskContext<u32> = TaskContext::new();
+    let cloned_task_context = task_context.clone();
+    task_context.spawn(async move {
+        let mut ticks = interval(Duration::from_millis(500));
+        for tick in 0.. {
+            ticks.tick().await;
+            cloned_task_context.dispatch(tick);
+        }
+    });
+
+    block_on(task_context.run_until_stalled());
+    assert_eq!(task_context.drain(), vec![0]);
+
+    clock.advance(Duration::from_millis(499));
+    block_on(task_context.run_until_stalled());
+    assert!(task_context.drain().is_empty());
+
+    clock.advance(Duration::from_millis(1001));
+    block_on(task_context.run_until_stalled());
+    assert_eq!(task_context.drain(), vec![1, 2, 3]);
+}
+
+#[test]
+fn skipping_fires_once_after_a_stall_and_keeps_the_schedule() {
+    let clock = ManualClock::new();
+    let _guard = set_clock(clock.clone());
+    let mut ticks = interval(Duration::from_nanos(3));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    ticks.tick().now_or_never().unwrap();
+
+    // a stall that misses over a trillion ticks fires only once
+    clock.advance(Duration::from_secs(3600) + Duration::from_nanos(1));
+    assert!(ticks.tick().now_or_never().is_some());
+    assert!(ticks.tick().now_or_never().is_none());
+
+    // and the schedule carries on where it would have been
+    clock.advance(Duration::from_nanos(2));
+    assert_eq!(ticks.tick().now_or_never(), Some(Instant::now()));
+}
+
+#[test]
+fn timeout_gives_up_on_slow_futures() {
+    let result = block_on(timeout(Duration::from_millis(10), sleep_ms(5000)));
+    assert!(result.is_err());
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "twirp")]
+
+use async_std::task::block_on;
+use protobuf::well_known_types::{StringValue, UInt32Value};
+use quicksilver_utils_async::request::twirp::*;
+use quicksilver_utils_async::request::*;
+use quicksilver_utils_async::twirp_service;
+
+twirp_service! {
+    /// Submits high scores
+    pub struct ScoresClient for "demo.scores.Scores" {
+        /// Submit a score, getting back its rank
+        rpc Submit(UInt32Value) returns (StringValue) as submit;
+        /// Clear the board
+        rpc Reset(UInt32Value) returns (StringValue) as reset;
+    }
+}
+
+/// A scores service that only ranks scores up to 9000 and lets no one
+/// reset the board
+fn client() -> ScoresClient<MockServiceClient> {
+    let server = MockServiceClient::new();
+    server.transport().on_call::<_, _, TwirpProtobuf, _>(
+        "http://localhost/twirp/demo.scores.Scores/Submit",
+        |score: UInt32Value| {
+            if score.get_value() > 9000 {
+                let body = r#"{"code":"out_of_range","msg":"nobody is that good"}"#;
+                return Err(RequestError::Http {
+                    status: http::StatusCode::BAD_REQUEST,
+                    body: body.into(),
+                });
+            }
+            let mut rank = StringValue::new();
+            rank.set_value("1st".to_string());
+            Ok(rank)
+        },
+    );
+    server
+        .transport()
+        .on_call::<_, StringValue, TwirpProtobuf, _>(
+            "http://localhost/twirp/demo.scores.Scores/Reset",
+            |_: UInt32Value| {
+                let body = r#"{"code":"unauthenticated","msg":"admins only"}"#;
+                Err(RequestError::Http {
+                    status: http::StatusCode::UNAUTHORIZED,
+                    body: body.into(),
+                })
+            },
+        );
+    ScoresClient::new(server, "http://localhost")
+}
+
+fn score(value: u32) -> UInt32Value {
+    let mut score = UInt32Value::new();
+    score.set_value(value);
+    score
+}
+
+#[test]
+fn rpcs_post_to_their_method() {
+    let rank = block_on(client().submit(&score(9000))).unwrap();
+    assert_eq!(rank.get_value(), "1st");
+}
+
+#[test]
+fn error_bodies_become_twirp_errors() {
+    match block_on(client().submit(&score(9001))) {
+        Err(RequestError::Twirp(error)) => {
+            assert_eq!(error.code, ErrorCode::OutOfRange);
+            assert_eq!(error.msg, "nobody is that good");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn unauthorized_keeps_the_twirp_error() {
+    match block_on(client().reset(&UInt32Value::new())) {
+        Err(RequestError::Twirp(error)) => {
+            assert_eq!(error.code, ErrorCode::Unauthenticated);
+            assert_eq!(error.msg, "admins only");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
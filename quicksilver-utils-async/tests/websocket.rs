@@ -0,0 +1,185 @@
+use async_std::io::prelude::*;
+use async_std::task::{block_on, spawn};
+use http::header::{HeaderName, HeaderValue, COOKIE, HOST};
+use quicksilver_utils_async::websocket::*;
+use std::time::Duration;
+
+mod common;
+
+use common::{accept_websocket, frame, listen, read_frame, read_head, websocket_response};
+
+#[test]
+fn receives_the_servers_close_code_and_reason() {
+    block_on(async {
+        let (listener, url) = listen("ws").await;
+        spawn(async move {
+            let mut stream = accept_websocket(&listener, "").await;
+            stream.write_all(&frame(0x1, b"hello")).await.unwrap();
+            let mut close = 4001u16.to_be_bytes().to_vec();
+            close.extend_from_slice(b"bye");
+            stream.write_all(&frame(0x8, &close)).await.unwrap();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+        });
+
+        let socket = WebSocket::connect(&url).await.unwrap();
+        assert_eq!(
+            socket.receive().await.unwrap(),
+            WebSocketMessage::String("hello".to_string())
+        );
+        assert_eq!(
+            socket.receive().await.unwrap(),
+            WebSocketMessage::Close {
+                code: 4001,
+                reason: "bye".to_string()
+            }
+        );
+        assert!(matches!(
+            socket.receive().await,
+            Err(WebSocketError::StateClosed)
+        ));
+    })
+}
+
+#[test]
+fn refuses_close_codes_it_cannot_send() {
+    block_on(async {
+        let (listener, url) = listen("ws").await;
+        let server = spawn(async move {
+            let mut stream = accept_websocket(&listener, "").await;
+            read_frame(&mut stream).await
+        });
+
+        let socket = WebSocket::connect(&url).await.unwrap();
+        let going_away = WebSocketMessage::Close {
+            code: 1001,
+            reason: String::new(),
+        };
+        assert!(matches!(
+            socket.send(&going_away).await,
+            Err(WebSocketError::NativeError(_))
+        ));
+
+        let normal = WebSocketMessage::Close {
+            code: 1000,
+            reason: String::new(),
+        };
+        socket.send(&normal).await.unwrap();
+        let (opcode, payload) = server.await;
+        assert_eq!(opcode, 0x8);
+        assert_eq!(payload, 1000u16.to_be_bytes());
+    })
+}
+
+#[test]
+fn sends_headers_and_agrees_on_a_subprotocol() {
+    block_on(async {
+        let (listener, url) = listen("ws").await;
+        let server = spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let head = read_head(&mut stream).await;
+            let response = websocket_response(&head, "Sec-WebSocket-Protocol: b\r\n");
+            // the first frame arrives with the handshake response
+            let mut bytes = response.into_bytes();
+            bytes.extend(frame(0x2, b"map"));
+            stream.write_all(&bytes).await.unwrap();
+            assert_eq!(read_frame(&mut stream).await.0, 0x8);
+            head
+        });
+
+        let config = WebSocketConfig::new().protocol("a").protocol("b").header(
+            HeaderName::from_static("x-player"),
+            HeaderValue::from_static("7"),
+        );
+        let socket = WebSocket::connect_with(&url, &config).await.unwrap();
+        assert_eq!(socket.protocol().as_deref(), Some("b"));
+        assert_eq!(
+            socket.receive().await.unwrap(),
+            WebSocketMessage::Binary(b"map"[..].into())
+        );
+        socket.close().await.unwrap();
+
+        let head = server.await.to_lowercase();
+        assert!(head.contains("\r\nx-player: 7\r\n"));
+        assert!(head.contains("\r\nsec-websocket-protocol: a,b\r\n"));
+    })
+}
+
+#[test]
+fn sends_configured_headers_even_when_rejected() {
+    block_on(async {
+        let (listener, url) = listen("ws").await;
+        let server = spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let head = read_head(&mut stream).await;
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n")
+                .await
+                .unwrap();
+            head
+        });
+
+        let config = WebSocketConfig::new().header(COOKIE, HeaderValue::from_static("session=abc"));
+        let rejected = WebSocket::connect_with(&url, &config).await;
+        assert!(matches!(
+            rejected,
+            Err(WebSocketError::Rejected { status: 403 })
+        ));
+        assert!(server.await.contains("\r\ncookie: session=abc\r\n"));
+    })
+}
+
+#[test]
+fn refuses_headers_the_handshake_sets() {
+    block_on(async {
+        let (_listener, url) = listen("ws").await;
+        let config = WebSocketConfig::new().header(HOST, HeaderValue::from_static("elsewhere"));
+        match WebSocket::connect_with(&url, &config).await {
+            Err(WebSocketError::NativeError(message)) => assert!(message.contains("host")),
+            _ => panic!("the handshake sets the host"),
+        }
+    })
+}
+
+#[test]
+fn trusts_nothing_without_the_built_in_roots() {
+    block_on(async {
+        let (_listener, url) = listen("wss").await;
+        let config = WebSocketConfig::new().tls_built_in_root_certs(false);
+        match WebSocket::connect_with(&url, &config).await {
+            Err(WebSocketError::NativeError(message)) => {
+                assert_eq!(message, "No root certificates to trust")
+            }
+            _ => panic!("nothing was trusted"),
+        }
+    })
+}
+
+#[test]
+fn times_out_once_the_server_stops_answering_pings() {
+    block_on(async {
+        let (listener, url) = listen("ws").await;
+        spawn(async move {
+            let mut stream = accept_websocket(&listener, "").await;
+            // answer the first ping, then go quiet
+            let (opcode, payload) = read_frame(&mut stream).await;
+            assert_eq!(opcode, 0x9);
+            stream.write_all(&frame(0xA, &payload)).await.unwrap();
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest).await;
+        });
+
+        let heartbeat = Heartbeat {
+            interval: Duration::from_millis(50),
+            max_missed: 0,
+            ..Heartbeat::default()
+        };
+        let config = WebSocketConfig::new().heartbeat(heartbeat);
+        let socket = WebSocket::connect_with(&url, &config).await.unwrap();
+        assert!(matches!(
+            socket.receive().await,
+            Err(WebSocketError::Timeout)
+        ));
+        assert!(socket.rtt().is_some());
+    })
+}
@@ -143,7 +143,7 @@ impl<'a> System<'a> for WasdMovement {
 
         let input_ctx: &mut InputContext = &mut input_ctx_resource;
 
-        let speed = 3.; // TODO, configurable per entity, and should also take into account tick delta
+        let speed = 3.; // TODO, configurable per entity. Pixels per update, so run this at a fixed timestep
         let mut velocity = [0., 0.];
 
         let input: &mut Input = &mut input_ctx.input;
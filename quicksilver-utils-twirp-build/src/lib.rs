@@ -0,0 +1,161 @@
+//! # quicksilver-utils-twirp-build
+//!
+//! Generates typed Twirp clients for `quicksilver-utils-async` from a
+//! service's `.proto` file, for use from a build script. The file is
+//! parsed with `protobuf-parse`, so imports, options and nested messages
+//! are handled as `protoc` would.
+//!
+//! Each service becomes a `twirp_service!` declaration named after the
+//! service with a `Client` suffix, and each RPC a snake_case method.
+//!
+//! # Examples
+//!
+//! In `build.rs`, after generating the messages into `src/proto`:
+//!
+//! ```no_run
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! let generated = quicksilver_utils_twirp_build::generate(
+//!     "protos/scores.proto",
+//!     &["protos"],
+//!     "crate::proto::scores",
+//! )
+//! .unwrap();
+//! std::fs::write(format!("{}/scores_twirp.rs", out_dir), generated).unwrap();
+//! println!("cargo:rerun-if-changed=protos/scores.proto");
+//! ```
+//!
+//! and then `include!(concat!(env!("OUT_DIR"), "/scores_twirp.rs"));`
+//! where the clients should live.
+
+use protobuf::descriptor::{FileDescriptorProto, MethodDescriptorProto};
+use protobuf_parse::Parser;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Generate a `twirp_service!` client for every service in the `.proto`
+/// file at <proto>, which imports from the directories <includes>
+///
+/// <proto> has to be inside one of <includes>. Messages of the file's
+/// own package are looked up in the Rust module at <messages>, where
+/// rust-protobuf generated them, and `google.protobuf` messages in
+/// `protobuf::well_known_types`. Streaming RPCs, which Twirp doesn't
+/// support, and messages of other packages are errors.
+pub fn generate(
+    proto: impl AsRef<Path>,
+    includes: &[impl AsRef<Path>],
+    messages: &str,
+) -> Result<String, String> {
+    let parsed = Parser::new()
+        .pure()
+        .includes(includes)
+        .input(proto.as_ref())
+        .parse_and_typecheck()
+        .map_err(|e| format!("{:#}", e))?;
+    let name = parsed
+        .relative_paths
+        .first()
+        .ok_or_else(|| format!("{} was not parsed", proto.as_ref().display()))?
+        .to_str()
+        .to_string();
+    let file = parsed
+        .file_descriptors
+        .iter()
+        .find(|file| file.name() == name)
+        .ok_or_else(|| format!("{} was not parsed", name))?;
+    services(file, messages)
+}
+
+fn services(file: &FileDescriptorProto, messages: &str) -> Result<String, String> {
+    let package = file.package();
+    let mut out = String::from("// Generated by quicksilver_utils_twirp_build::generate\n");
+    for service in &file.service {
+        let full_name = if package.is_empty() {
+            service.name().to_string()
+        } else {
+            format!("{}.{}", package, service.name())
+        };
+        out.push_str("\n::quicksilver_utils_async::twirp_service! {\n");
+        writeln!(out, "    /// A client for the `{}` service", full_name).unwrap();
+        writeln!(
+            out,
+            "    pub struct {}Client for \"{}\" {{",
+            service.name(),
+            full_name
+        )
+        .unwrap();
+        for method in &service.method {
+            writeln!(out, "        /// Call `{}`", method.name()).unwrap();
+            writeln!(out, "        {}", rpc(method, package, messages)?).unwrap();
+        }
+        out.push_str("    }\n}\n");
+    }
+    Ok(out)
+}
+
+/// The `rpc` line of `twirp_service!` for <method>
+fn rpc(method: &MethodDescriptorProto, package: &str, messages: &str) -> Result<String, String> {
+    if method.client_streaming() || method.server_streaming() {
+        return Err(format!(
+            "rpc {} streams, which Twirp doesn't support",
+            method.name()
+        ));
+    }
+    Ok(format!(
+        "rpc {}({}) returns ({}) as {};",
+        method.name(),
+        message_type(method.input_type(), package, messages)?,
+        message_type(method.output_type(), package, messages)?,
+        method_name(method.name())
+    ))
+}
+
+/// The Rust path of the message with the fully qualified <name>, as
+/// rust-protobuf generates it
+fn message_type(name: &str, package: &str, messages: &str) -> Result<String, String> {
+    let name = name.trim_start_matches('.');
+    if let Some(message) = name.strip_prefix("google.protobuf.") {
+        return Ok(format!("::protobuf::well_known_types::{}", message));
+    }
+    let local = if package.is_empty() {
+        Some(name)
+    } else {
+        name.strip_prefix(package)
+            .and_then(|rest| rest.strip_prefix('.'))
+    };
+    match local {
+        // nested messages are flattened to Outer_Inner
+        Some(local) => Ok(format!("{}::{}", messages, local.replace('.', "_"))),
+        None => Err(format!("message {} is not from package {}", name, package)),
+    }
+}
+
+/// <name> in snake_case, as a Rust identifier
+fn method_name(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "static", "struct", "super", "trait", "true",
+        "type", "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final",
+        "macro", "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+    ];
+    if KEYWORDS.contains(&snake.as_str()) {
+        format!("r#{}", snake)
+    } else {
+        snake
+    }
+}
@@ -0,0 +1,37 @@
+use quicksilver_utils_twirp_build::generate;
+
+const PROTOS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/protos");
+
+fn proto(name: &str) -> String {
+    format!("{}/demo/{}", PROTOS, name)
+}
+
+#[test]
+fn declares_a_client_per_service() {
+    let generated = generate(proto("scores.proto"), &[PROTOS], "crate::proto::scores").unwrap();
+    assert!(generated.contains(r#"pub struct ScoresClient for "demo.scores.Scores" {"#));
+    assert!(generated.contains(
+        "rpc SubmitScore(crate::proto::scores::Score) \
+         returns (::protobuf::well_known_types::StringValue) as submit_score;"
+    ));
+}
+
+#[test]
+fn flattens_nested_messages_and_escapes_keywords() {
+    let generated = generate(proto("scores.proto"), &[PROTOS], "crate::proto::scores").unwrap();
+    assert!(generated.contains(
+        "rpc Type(crate::proto::scores::Score_Player) \
+         returns (crate::proto::scores::Empty) as r#type;"
+    ));
+}
+
+#[test]
+fn rejects_streaming_rpcs() {
+    let error = generate(proto("streams.proto"), &[PROTOS], "crate::proto::streams").unwrap_err();
+    assert_eq!(error, "rpc Watch streams, which Twirp doesn't support");
+}
+
+#[test]
+fn reports_parse_errors() {
+    assert!(generate(proto("missing.proto"), &[PROTOS], "crate::proto").is_err());
+}